@@ -1,144 +1,1962 @@
-use crate::epub::content::RenderableChapter;
+mod cache;
+pub mod input;
+pub mod keymap;
+mod prefetch;
+mod stats;
+
+use crate::command::Command as ReaderCommand;
+use crate::config::{Config, ProgressMode, TextDirection};
+use crate::epub::content::{RenderableBlock, RenderableChapter, TextRun, runs_text};
 use crate::epub::handler::EpubHandler;
-use crate::epub::processor::process_chapter_html;
-use std::path::PathBuf;
+use crate::epub::processor::{ProcessOptions, process_chapter_html};
+use crate::image_handler::{image_info, load_image};
+use crate::locator::Locator;
+use crate::persistence::{
+  Annotation, BookState, Bookmark, Highlight, Position, RecentBook, RecentBooks, append_stats_log,
+};
+use crate::reader::renderer::ChapterLayout;
+use crate::reader::search::SearchPattern;
+use crate::reader::theme::Palette;
+use crate::timing;
+use crate::ui::InputMode;
+use cache::{ChapterCache, SpineItem};
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use input::{CursorMove, TextInput};
+use keymap::Keymap;
+use prefetch::Prefetcher;
+use ratatui_image::picker::Picker;
+use ratatui_image::protocol::StatefulProtocol;
+use stats::SessionStats;
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Instant;
+
+/// Lines of dictionary output, or why the lookup failed
+type LookupResult = Result<Vec<String>, String>;
+
+/// How many processed spine items are kept around for revisiting
+const CHAPTER_CACHE_SIZE: usize = 16;
+
+/// What a text prompt is collecting input for
+pub enum PromptKind {
+  Annotation,
+  Bookmark,
+  Command,
+  Search { regex: bool },
+}
+
+impl PromptKind {
+  pub fn label(&self) -> &'static str {
+    match self {
+      PromptKind::Annotation => "Note",
+      PromptKind::Bookmark => "Bookmark name",
+      PromptKind::Command => "",
+      PromptKind::Search { regex: false } => "Search (Tab: regex)",
+      PromptKind::Search { regex: true } => "Regex search (Tab: plain)",
+    }
+  }
+}
+
+/// A single-line text prompt shown in the footer
+pub struct Prompt {
+  pub kind: PromptKind,
+  pub input: TextInput,
+}
+
+/// Which list a popup overlay is showing
+pub enum OverlayKind {
+  Toc,
+  Bookmarks,
+  Annotations,
+  Highlights,
+  Diagnostics,
+  Footnotes,
+  Lookup(String, Vec<String>), // Looked-up word and the dictionary output
+  Stats,
+  Outline,
+  ImageUnsupported(PathBuf), // Extracted image the terminal can't draw
+  ChapterFinder(String),     // Filter typed so far
+}
+
+/// Troubleshooting views shown instead of the formatted chapter
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+  Blocks, // The parsed blocks, `Debug`-printed
+  Html,   // The chapter's source
+}
+
+/// An image shown over the whole screen
+pub struct ImageView {
+  pub path: PathBuf,
+  pub image: Result<StatefulProtocol, String>, // Decoded for the terminal, or why it couldn't be
+}
+
+/// A popup list drawn over the chapter
+pub struct Overlay {
+  pub kind: OverlayKind,
+  pub selected: usize,
+}
 
 pub struct AppState {
+  pub config: Config,
   pub epub_handler: EpubHandler,
   pub current_chapter_index: usize,
   pub renderable_chapter: RenderableChapter,
   pub should_quit: bool,
+  pub pending_quit: bool, // Waiting for the user to confirm quitting
   pub scroll_position: usize,
   pub image_paths: Vec<String>, // Store paths to images in the current chapter
   pub current_image_index: usize, // Index of the currently selected image
   pub extracted_images: Vec<PathBuf>, // Store paths to extracted images
+  pub image_info: HashMap<PathBuf, Option<String>>, // Probed dimensions and size of extracted images
+  pub resource_errors: Vec<(String, String)>, // (resource path, error) for the current chapter
+  pub chapter_layout: ChapterLayout,          // Layout recorded during the last render
+  pub book_state: BookState,                  // Annotations and other per-book data
+  pub prompt: Option<Prompt>,
+  pub overlay: Option<Overlay>,
+  pub image_view: Option<ImageView>,
+  pub status_message: Option<String>, // Transient message shown in the footer
+  pub highlight_start: Option<usize>, // First line of a highlight being selected
+  pub word_cursor: Option<(usize, usize)>, // (content line, word index) for lookups
+  pub show_line_numbers: bool,
+  pub show_header: bool,
+  pub show_footer: bool,
+  pub show_outline: bool, // Whether the heading outline sidebar is open
+  pub show_images: bool,  // Whether image placeholders are laid out with the text
+  pub palette: Palette,
+  pub debug_view: Option<DebugView>,
+  pub debug_text: Vec<String>, // Lines of the debug view, when one is shown
+  pub image_picker: Option<Picker>, // Image protocol of the terminal, if it can show images
+  pub selected_link: Option<usize>, // Index into the layout's links, for following
+  pub code_scroll: usize,      // Columns code blocks are scrolled sideways by
+  pub search: Option<SearchPattern>, // Active search, highlighted in the chapter
+  pub stats: SessionStats,
+  pub keymap: Keymap,
+  search_match: Option<usize>, // Index of the match last jumped to
+  tts_process: Option<Child>,  // Running text-to-speech command
+  lookup: Option<(String, Receiver<LookupResult>)>, // Word being looked up and its answer
+  chapter_cache: ChapterCache,
+  prefetcher: Prefetcher, // Processes neighbouring chapters in the background
+  pending_scroll: Option<f64>, // Fraction of the chapter to scroll to once it is laid out
+  pending_anchor: Option<usize>, // Block a followed link points at, likewise
+  item_lengths: Vec<(usize, usize)>, // Spine index and text length of each item shown
+  chapter_loading: bool,  // Part of the current chapter is still processing in the background
+  content_version: u64,   // Bumped whenever the text to lay out changes
+  theme_changed: bool,    // The palette has to be chosen again
 }
 
 impl AppState {
-  pub fn new(mut epub_handler: EpubHandler, initial_chapter: usize) -> Result<Self, String> {
-    let raw_html = epub_handler.get_chapter_content_raw(initial_chapter)?;
-    let renderable_chapter = process_chapter_html(&raw_html);
+  pub fn new(
+    mut epub_handler: EpubHandler,
+    initial_chapter: Option<usize>,
+    mut config: Config,
+  ) -> Result<Self, String> {
+    let chapter_count = epub_handler.get_chapter_count();
+    if let Some(chapter) = initial_chapter
+      && chapter >= chapter_count
+    {
+      return Err(format!(
+        "Chapter {} is out of range; the book has {} chapters",
+        chapter, chapter_count
+      ));
+    }
 
-    // Extract image paths from the chapter
-    let image_paths: Vec<String> = renderable_chapter
-      .blocks
-      .iter()
-      .filter_map(|block| match block {
-        crate::epub::content::RenderableBlock::Image(path) => Some(path.clone()),
-        _ => None,
-      })
-      .collect();
+    if needs_chapter_lengths(&config) {
+      epub_handler.compute_chapter_lengths();
+    }
 
-    // Extract images to temporary files
-    let mut extracted_images = Vec::new();
-    for image_path in &image_paths {
-      match epub_handler.extract_resource(image_path) {
-        Ok(path) => extracted_images.push(path),
-        Err(e) => {
-          eprintln!("Warning: Failed to extract image {}: {}", image_path, e);
-          // We'll add a placeholder path for now
-          extracted_images.push(PathBuf::from(""));
-        }
+    let book_state = BookState::load(&epub_handler.base_path);
+
+    // Without an explicit chapter, resume where the book was left
+    let (initial_chapter, initial_fraction) = match (initial_chapter, book_state.last_position) {
+      (Some(chapter), _) => (chapter, None),
+      (None, Some(position)) if position.chapter < chapter_count => {
+        (position.chapter, Some(position.fraction))
       }
+      (None, _) if config.skip_front_matter => (epub_handler.first_content_chapter(), None),
+      (None, _) => {
+        let first = (0..chapter_count).find(|&index| !epub_handler.is_navigation(index));
+        (first.unwrap_or(0), None)
+      }
+    };
+    // Settings chosen for this book win over the config file
+    let preferences = &book_state.preferences;
+    if let Some(indent) = preferences.paragraph_indent {
+      config.paragraph_indent = indent;
+    }
+    if let Some(theme) = preferences.theme {
+      config.theme = theme;
     }
+    if let Some(max_width) = preferences.max_width {
+      config.max_width = max_width;
+    }
+    let show_line_numbers = preferences.line_numbers.unwrap_or(config.line_numbers);
+    let show_images = preferences.show_images.unwrap_or(true);
+    let (show_header, show_footer) = (config.show_header, config.show_footer);
+    let show_outline = config.outline;
+    let keymap = Keymap::new(&config.keys)?;
+    let prefetcher = Prefetcher::new(epub_handler.stylesheet().clone(), process_options(&config));
 
-    Ok(AppState {
+    let mut app_state = AppState {
+      config,
       epub_handler,
       current_chapter_index: initial_chapter,
-      renderable_chapter,
+      renderable_chapter: RenderableChapter {
+        blocks: Vec::new(),
+        rtl: false,
+        footnotes: Vec::new(),
+        title: None,
+        links: Vec::new(),
+        anchors: HashMap::new(),
+      },
       should_quit: false,
+      pending_quit: false,
       scroll_position: 0,
-      image_paths,
+      image_paths: Vec::new(),
       current_image_index: 0,
-      extracted_images,
-    })
+      extracted_images: Vec::new(),
+      image_info: HashMap::new(),
+      resource_errors: Vec::new(),
+      chapter_layout: ChapterLayout::default(),
+      book_state,
+      prompt: None,
+      overlay: None,
+      image_view: None,
+      status_message: None,
+      highlight_start: None,
+      word_cursor: None,
+      show_line_numbers,
+      show_header,
+      show_footer,
+      show_outline,
+      show_images,
+      palette: Palette::default(),
+      debug_view: None,
+      debug_text: Vec::new(),
+      image_picker: None,
+      selected_link: None,
+      code_scroll: 0,
+      search: None,
+      stats: SessionStats::new(),
+      keymap,
+      search_match: None,
+      tts_process: None,
+      lookup: None,
+      chapter_cache: ChapterCache::new(CHAPTER_CACHE_SIZE),
+      prefetcher,
+      pending_scroll: initial_fraction,
+      pending_anchor: None,
+      item_lengths: Vec::new(),
+      chapter_loading: false,
+      content_version: 0,
+      theme_changed: false,
+    };
+    app_state.load_current_chapter()?;
+
+    Ok(app_state)
+  }
+
+  pub fn input_mode(&self) -> InputMode {
+    if self.pending_quit {
+      InputMode::ConfirmQuit
+    } else if self.image_view.is_some() {
+      InputMode::Image
+    } else if self.prompt.is_some() {
+      InputMode::Prompt
+    } else if let Some(overlay) = &self.overlay {
+      match overlay.kind {
+        OverlayKind::ChapterFinder(_) => InputMode::Finder,
+        _ => InputMode::Overlay,
+      }
+    } else {
+      InputMode::Reading
+    }
+  }
+
+  /// Quit, or ask first when the config wants confirmation
+  pub fn request_quit(&mut self) {
+    if self.config.confirm_quit {
+      self.pending_quit = true;
+      self.status_message = Some("Quit? (y/n)".to_string());
+    } else {
+      self.should_quit = true;
+    }
+  }
+
+  /// Move to an arbitrary chapter and scroll position
+  pub fn jump_to(&mut self, chapter: usize, scroll: usize) -> Result<(), String> {
+    if chapter != self.current_chapter_index {
+      if chapter >= self.epub_handler.get_chapter_count() {
+        return Err(format!("Chapter index {} out of bounds", chapter));
+      }
+      self.current_chapter_index = chapter;
+      self.load_current_chapter()?;
+      self.current_image_index = 0;
+      self.chapter_layout = ChapterLayout::default();
+      self.highlight_start = None;
+      self.word_cursor = None;
+      self.selected_link = None;
+    }
+    self.scroll_position = scroll;
+    Ok(())
+  }
+
+  fn run_command(&mut self, command: ReaderCommand) {
+    let result = match command {
+      ReaderCommand::GoToPercent(percent) => self.go_to_percent(percent),
+      ReaderCommand::ParagraphIndent(indent) => {
+        self.config.paragraph_indent = indent;
+        self.content_version += 1;
+        self.book_state.preferences.paragraph_indent = Some(indent);
+        self.save_book_state(&format!("Paragraphs indented by {} for this book", indent));
+        Ok(())
+      }
+      ReaderCommand::Theme(theme) => {
+        self.config.theme = theme;
+        self.theme_changed = true;
+        self.book_state.preferences.theme = Some(theme);
+        self.save_book_state(&format!("{:?} theme for this book", theme));
+        Ok(())
+      }
+      ReaderCommand::MaxWidth(max_width) => {
+        self.config.max_width = max_width;
+        self.book_state.preferences.max_width = Some(max_width);
+        let message = match max_width {
+          0 => "Text fills the screen for this book".to_string(),
+          width => format!("Text kept within {} columns for this book", width),
+        };
+        self.save_book_state(&message);
+        Ok(())
+      }
+    };
+    if let Err(e) = result {
+      self.status_message = Some(e);
+    }
+  }
+
+  /// Jump to a point in the book by length-weighted progress
+  pub fn go_to_percent(&mut self, percent: f64) -> Result<(), String> {
+    let fraction = (percent / 100.0).clamp(0.0, 1.0);
+    let count = self.epub_handler.get_chapter_count();
+    self.epub_handler.compute_chapter_lengths();
+    let lengths = self.epub_handler.get_chapter_lengths();
+    let total: usize = lengths.iter().sum();
+
+    // Without any text, fall back to spine positions; 0% is always the very start
+    let (target, offset) = if total == 0 || fraction == 0.0 {
+      let target = (fraction * count.saturating_sub(1) as f64).round() as usize;
+      (target, 0.0)
+    } else {
+      let wanted = fraction * total as f64;
+      let mut before = 0.0;
+      let mut target = (count - 1, 0.0);
+      for (index, &length) in lengths.iter().enumerate() {
+        if length > 0 && before + length as f64 >= wanted {
+          target = (index, wanted - before);
+          break;
+        }
+        before += length as f64;
+      }
+      target
+    };
+
+    // Position inside the logical chapter holding the target spine item
+    let range = self.range_of(target);
+    let lengths = self.epub_handler.get_chapter_lengths();
+    let chapter_total: usize = lengths.get(range.clone()).map_or(0, |l| l.iter().sum());
+    let chapter_before: usize = lengths
+      .get(range.start..target)
+      .map_or(0, |l| l.iter().sum());
+    let chapter_fraction = match chapter_total {
+      0 => 0.0,
+      total => ((chapter_before as f64 + offset) / total as f64).clamp(0.0, 1.0),
+    };
+
+    self.jump_to(range.start, 0)?;
+    // The scroll position depends on the chapter's layout, known after the next render
+    self.pending_scroll = Some(chapter_fraction);
+    self.chapter_layout = ChapterLayout::default();
+    Ok(())
+  }
+
+  /// Jump to a locator, scrolling to its share of the chapter's text
+  pub fn go_to_locator(&mut self, locator: &Locator) -> Result<(), String> {
+    let index = self
+      .epub_handler
+      .spine_index(&locator.path)
+      .ok_or_else(|| format!("No spine item {} in this book", locator.path))?;
+
+    self.jump_to(self.range_of(index).start, 0)?;
+    let before: usize = self
+      .item_lengths
+      .iter()
+      .take_while(|&&(item, _)| item != index)
+      .map(|&(_, length)| length)
+      .sum();
+    let total = self.renderable_chapter.text_len();
+    let fraction = match total {
+      0 => 0.0,
+      total => ((before + locator.offset) as f64 / total as f64).min(1.0),
+    };
+
+    self.pending_scroll = Some(fraction);
+    self.chapter_layout = ChapterLayout::default();
+    Ok(())
+  }
+
+  /// Finish a pending jump now that the chapter has been laid out, returning
+  /// whether the scroll position changed
+  pub fn apply_pending_scroll(&mut self) -> bool {
+    if let Some(block) = self.pending_anchor.take()
+      && let Some(&line) = self.chapter_layout.block_offsets.get(block)
+    {
+      self.scroll_to_line(line);
+      return true;
+    }
+    let Some(fraction) = self.pending_scroll.take() else {
+      return false;
+    };
+    let layout = &self.chapter_layout;
+    let last_top = layout.total_lines.saturating_sub(layout.viewport_height);
+    self.scroll_position = ((layout.total_lines as f64 * fraction) as usize).min(last_top);
+    true
   }
 
   pub fn next_chapter(&mut self) -> Result<(), String> {
-    if self.current_chapter_index + 1 < self.epub_handler.get_chapter_count() {
-      self.current_chapter_index += 1;
+    let mut next = self.chapter_range().end;
+    // One turn leaves the front matter behind
+    if self.config.skip_front_matter {
+      next = next.max(self.epub_handler.first_content_chapter());
+    }
+    // The nav document is passed over; it is a list of links, not text
+    let next = (next..self.epub_handler.get_chapter_count())
+      .find(|&index| !self.epub_handler.is_navigation(index));
+    if let Some(next) = next {
+      self.current_chapter_index = next;
       self.load_current_chapter()?;
+      self.stats.chapters_advanced += 1;
       self.scroll_position = 0; // Reset scroll when changing chapters
       self.current_image_index = 0; // Reset image index when changing chapters
+      self.chapter_layout = ChapterLayout::default();
+      self.highlight_start = None;
+      self.word_cursor = None;
+      self.selected_link = None;
+      self.note_furthest();
+    } else if self.config.wrap_around && self.chapter_range().start > 0 {
+      let first = (0..self.chapter_range().start)
+        .find(|&index| !self.epub_handler.is_navigation(index))
+        .unwrap_or(0);
+      self.jump_to(first, 0)?;
+      self.status_message = Some("Wrapped to the beginning of the book".to_string());
+    } else {
+      self.status_message = Some("End of book".to_string());
     }
     Ok(())
   }
 
   pub fn previous_chapter(&mut self) -> Result<(), String> {
-    if self.current_chapter_index > 0 {
-      self.current_chapter_index -= 1;
+    let start = self.chapter_range().start;
+    let previous = (0..start)
+      .rev()
+      .find(|&index| !self.epub_handler.is_navigation(index));
+    if let Some(previous) = previous {
+      self.current_chapter_index = previous;
       self.load_current_chapter()?;
       self.scroll_position = 0; // Reset scroll when changing chapters
       self.current_image_index = 0; // Reset image index when changing chapters
+      self.chapter_layout = ChapterLayout::default();
+      self.highlight_start = None;
+      self.word_cursor = None;
+      self.selected_link = None;
+    } else if self.config.wrap_around
+      && self.chapter_range().end < self.epub_handler.get_chapter_count()
+    {
+      let last = self.epub_handler.get_chapter_count() - 1;
+      self.jump_to(last, 0)?;
+      self.status_message = Some("Wrapped to the end of the book".to_string());
+    } else {
+      self.status_message = Some("Beginning of book".to_string());
     }
     Ok(())
   }
 
+  /// Spine items that make up the current logical chapter
+  fn chapter_range(&self) -> Range<usize> {
+    self.range_of(self.current_chapter_index)
+  }
+
   fn load_current_chapter(&mut self) -> Result<(), String> {
-    let raw_html = self
-      .epub_handler
-      .get_chapter_content_raw(self.current_chapter_index)?;
-    self.renderable_chapter = process_chapter_html(&raw_html);
+    let mut blocks = Vec::new();
+    let mut rtl = false;
+    let mut footnotes = Vec::new();
+    let mut title = None;
+    let mut links = Vec::new();
+    let mut anchors = HashMap::new();
+    self.chapter_loading = false;
+    self.item_lengths.clear();
+    self.image_paths.clear();
+    self.extracted_images.clear();
+    self.resource_errors.clear();
+    self.code_scroll = 0;
 
-    // Extract image paths from the chapter
-    self.image_paths = self
-      .renderable_chapter
+    for index in self.chapter_range() {
+      let item = match self.chapter_cache.get(index) {
+        Some(item) => item,
+        None => match self.load_spine_item(index)? {
+          Some(item) => {
+            let item = Rc::new(item);
+            self.chapter_cache.insert(index, Rc::clone(&item));
+            item
+          }
+          None => {
+            self.chapter_loading = true;
+            continue;
+          }
+        },
+      };
+
+      for (image_path, image) in item.image_paths.iter().zip(&item.images) {
+        match image {
+          Ok(path) => {
+            self
+              .image_info
+              .entry(path.clone())
+              .or_insert_with(|| image_info(path));
+            self.extracted_images.push(path.clone());
+          }
+          Err(e) => {
+            // Keep a placeholder so indices still line up with the image blocks
+            self.extracted_images.push(PathBuf::from(""));
+            self.resource_errors.push((image_path.clone(), e.clone()));
+          }
+        }
+      }
+
+      self.image_paths.extend(item.image_paths.iter().cloned());
+      self.item_lengths.push((index, item.chapter.text_len()));
+      // Each spine item numbers its links and blocks from zero
+      let link_offset = links.len();
+      let block_offset = blocks.len();
+      anchors.extend(
+        item
+          .chapter
+          .anchors
+          .iter()
+          .map(|(anchor, block)| (anchor.clone(), block + block_offset)),
+      );
+      blocks.extend(item.chapter.blocks.iter().map(|block| {
+        let mut block = block.clone();
+        block.offset_links(link_offset);
+        block
+      }));
+      links.extend(item.chapter.links.iter().cloned());
+      rtl |= item.chapter.rtl;
+      footnotes.extend(item.chapter.footnotes.iter().cloned());
+      title = title.or_else(|| item.chapter.title.clone());
+    }
+
+    if self.chapter_loading {
+      blocks.push(RenderableBlock::Paragraph(vec![TextRun::plain(
+        "Large chapter, loading…",
+      )]));
+      self.status_message = Some("Large chapter, loading…".to_string());
+    }
+
+    self.renderable_chapter = RenderableChapter {
+      blocks,
+      rtl,
+      footnotes,
+      title,
+      links,
+      anchors,
+    };
+    self.prefetch_neighbors();
+    self.refresh_debug_text();
+    self.content_version += 1;
+
+    Ok(())
+  }
+
+  /// Process one spine item and extract its images, or hand it to the
+  /// background thread (returning `None`) when it is too large to process
+  /// without freezing the UI
+  fn load_spine_item(&mut self, index: usize) -> Result<Option<SpineItem>, String> {
+    let raw_html = self.epub_handler.get_chapter_content_raw(index)?;
+    if raw_html.len() > self.config.large_chapter_size {
+      if !self.prefetcher.is_pending(index) {
+        self.prefetcher.request(index, raw_html);
+      }
+      return Ok(None);
+    }
+
+    let chapter = process_chapter_html(
+      &raw_html,
+      self.epub_handler.stylesheet(),
+      &process_options(&self.config),
+    );
+    Ok(Some(self.extract_images(chapter)))
+  }
+
+  /// Extract the images and resolve the links of a processed spine item, which
+  /// must be the handler's current one so relative paths resolve against it
+  fn extract_images(&mut self, mut chapter: RenderableChapter) -> SpineItem {
+    for link in &mut chapter.links {
+      *link = self.epub_handler.resolve_link(link);
+    }
+    // Anchors are keyed like the links pointing at them, by path and fragment
+    chapter.anchors = std::mem::take(&mut chapter.anchors)
+      .into_iter()
+      .map(|(id, block)| (self.epub_handler.resolve_link(&format!("#{}", id)), block))
+      .collect();
+
+    let image_paths: Vec<String> = chapter
       .blocks
       .iter()
       .filter_map(|block| match block {
-        crate::epub::content::RenderableBlock::Image(path) => Some(path.clone()),
+        RenderableBlock::Image { src, .. } => Some(src.clone()),
         _ => None,
       })
       .collect();
 
-    // Extract images to temporary files
-    self.extracted_images.clear();
-    for image_path in &self.image_paths {
-      match self.epub_handler.extract_resource(image_path) {
-        Ok(path) => self.extracted_images.push(path),
-        Err(e) => {
-          eprintln!("Warning: Failed to extract image {}: {}", image_path, e);
-          // We'll add a placeholder path for now
-          self.extracted_images.push(PathBuf::from(""));
-        }
+    let started = Instant::now();
+    let images = image_paths
+      .iter()
+      .map(|image_path| self.epub_handler.extract_resource(image_path))
+      .collect();
+    timing::record(started, || format!("extract {} images", image_paths.len()));
+
+    SpineItem {
+      chapter,
+      image_paths,
+      images,
+    }
+  }
+
+  /// Reopen the book from disk, staying at the same chapter and scroll position
+  /// when they still exist
+  pub fn reload_book(&mut self) {
+    // A file that is being written may not open yet, so try a few times
+    const ATTEMPTS: usize = 3;
+    let path = self.epub_handler.base_path.clone();
+    let mut result = EpubHandler::open(path.clone());
+    for _ in 1..ATTEMPTS {
+      if result.is_ok() {
+        break;
       }
+      std::thread::sleep(std::time::Duration::from_millis(200));
+      result = EpubHandler::open(path.clone());
     }
+    let mut handler = match result {
+      Ok(handler) => handler,
+      Err(e) => {
+        self.status_message = Some(format!("Reload failed: {}", e));
+        return;
+      }
+    };
 
-    Ok(())
+    if needs_chapter_lengths(&self.config) {
+      handler.compute_chapter_lengths();
+    }
+    self.prefetcher = Prefetcher::new(handler.stylesheet().clone(), process_options(&self.config));
+    self.chapter_cache = ChapterCache::new(CHAPTER_CACHE_SIZE);
+    self.image_info.clear();
+    self.current_chapter_index = self
+      .current_chapter_index
+      .min(handler.get_chapter_count() - 1);
+    self.epub_handler = handler;
+    self.current_image_index = 0;
+    self.highlight_start = None;
+    self.word_cursor = None;
+    self.selected_link = None;
+
+    self.status_message = Some(match self.load_current_chapter() {
+      Ok(()) => "Book reloaded".to_string(),
+      Err(e) => e,
+    });
+  }
+
+  /// Queue the chapters either side of the current one for background processing
+  fn prefetch_neighbors(&mut self) {
+    let range = self.chapter_range();
+    let count = self.epub_handler.get_chapter_count();
+    let mut neighbors = Vec::new();
+    if range.end < count {
+      neighbors.extend(self.range_of(range.end));
+    }
+    if range.start > 0 {
+      neighbors.extend(self.range_of(range.start - 1));
+    }
+
+    for index in neighbors {
+      if self.prefetcher.is_pending(index) || self.chapter_cache.contains(index) {
+        continue;
+      }
+      // Reading stays on this thread since the document isn't `Send`
+      if let Ok(raw_html) = self.epub_handler.get_chapter_content_raw(index) {
+        self.prefetcher.request(index, raw_html);
+      }
+    }
+  }
+
+  /// Spine items of the logical chapter containing `index`
+  fn range_of(&self, index: usize) -> Range<usize> {
+    if self.config.group_by_toc {
+      self.epub_handler.chapter_group(index)
+    } else {
+      index..index + 1
+    }
+  }
+
+  /// Move chapters finished in the background into the cache
+  pub fn receive_prefetched(&mut self) {
+    let mut reload = false;
+    while let Some((index, chapter)) = self.prefetcher.try_recv() {
+      if self.epub_handler.select_chapter(index).is_ok() {
+        let item = self.extract_images(chapter);
+        self.chapter_cache.insert(index, Rc::new(item));
+        reload |= self.chapter_loading && self.chapter_range().contains(&index);
+      }
+    }
+
+    // Replace the loading notice once a large chapter is ready
+    if reload {
+      self.status_message = None;
+      if let Err(e) = self.load_current_chapter() {
+        self.status_message = Some(e);
+      }
+    }
   }
 
   pub fn scroll_down(&mut self) {
-    // We'll implement scrolling in the renderer
-    self.scroll_position = self.scroll_position.saturating_add(1);
+    self.scroll_to(self.scroll_position.saturating_add(self.config.scroll_step));
   }
 
   pub fn scroll_up(&mut self) {
-    self.scroll_position = self.scroll_position.saturating_sub(1);
+    self.scroll_to(self.scroll_position.saturating_sub(self.config.scroll_step));
+  }
+
+  pub fn page_down(&mut self) {
+    self.scroll_to(self.scroll_position.saturating_add(self.page_size()));
+  }
+
+  pub fn page_up(&mut self) {
+    self.scroll_to(self.scroll_position.saturating_sub(self.page_size()));
+  }
+
+  // Half of the text area, which grows when the header or footer is hidden
+  fn page_size(&self) -> usize {
+    (self.chapter_layout.viewport_height / 2).max(1)
+  }
+
+  // Scroll within the chapter, counting the lines moved; the renderer clamps
+  // the position to the chapter
+  fn scroll_to(&mut self, position: usize) {
+    let last_line = self.chapter_layout.total_lines.saturating_sub(1);
+    let from = self.scroll_position.min(last_line);
+    self.stats.lines_scrolled += from.abs_diff(position.min(last_line));
+    self.scroll_position = position;
+    self.note_furthest();
+  }
+
+  fn current_position(&self) -> Position {
+    Position {
+      chapter: self.current_chapter_index,
+      fraction: self.chapter_fraction(),
+    }
+  }
+
+  /// Move the furthest-read marker up to the current position if it is beyond it
+  fn note_furthest(&mut self) {
+    let current = self.current_position();
+    if self
+      .book_state
+      .furthest_position
+      .is_none_or(|furthest| current.is_past(&furthest))
+    {
+      self.book_state.furthest_position = Some(current);
+    }
+  }
+
+  /// Select the image whose line offset is nearest the current scroll position
+  pub fn select_image_near_scroll(&mut self) {
+    if let Some((index, _)) = self
+      .chapter_layout
+      .image_offsets
+      .iter()
+      .enumerate()
+      .min_by_key(|(_, offset)| offset.abs_diff(self.scroll_position))
+    {
+      self.current_image_index = index;
+    }
+  }
+
+  pub fn start_prompt(&mut self, kind: PromptKind) {
+    self.status_message = None;
+    self.prompt = Some(Prompt {
+      kind,
+      input: TextInput::default(),
+    });
+  }
+
+  pub fn prompt_input(&mut self, c: char) {
+    if let Some(prompt) = &mut self.prompt {
+      prompt.input.insert(c);
+    } else if let Some(overlay) = &mut self.overlay
+      && let OverlayKind::ChapterFinder(query) = &mut overlay.kind
+    {
+      query.push(c);
+      overlay.selected = 0;
+    }
+  }
+
+  pub fn prompt_backspace(&mut self) {
+    if let Some(prompt) = &mut self.prompt {
+      prompt.input.backspace();
+    } else if let Some(overlay) = &mut self.overlay
+      && let OverlayKind::ChapterFinder(query) = &mut overlay.kind
+    {
+      query.pop();
+      overlay.selected = 0;
+    }
+  }
+
+  /// Move the cursor within the prompt's text
+  pub fn prompt_cursor(&mut self, to: CursorMove) {
+    if let Some(prompt) = &mut self.prompt {
+      prompt.input.move_cursor(to);
+    }
+  }
+
+  /// Insert pasted text into the prompt or the chapter finder
+  pub fn prompt_paste(&mut self, text: &str) {
+    if let Some(prompt) = &mut self.prompt {
+      prompt.input.paste(text);
+    } else {
+      for c in text.chars().filter(|c| !c.is_control()) {
+        self.prompt_input(c);
+      }
+    }
+  }
+
+  /// Switch the search prompt between plain and regex matching
+  pub fn toggle_prompt_mode(&mut self) {
+    if let Some(Prompt {
+      kind: PromptKind::Search { regex },
+      ..
+    }) = &mut self.prompt
+    {
+      *regex = !*regex;
+    }
+  }
+
+  fn submit_prompt(&mut self) {
+    let Some(prompt) = self.prompt.take() else {
+      return;
+    };
+    let input = prompt.input.text().trim().to_string();
+
+    match prompt.kind {
+      // An empty search clears the current one
+      PromptKind::Search { .. } if input.is_empty() => self.search = None,
+      _ if input.is_empty() => {}
+      PromptKind::Search { regex } => match SearchPattern::new(&input, regex) {
+        Ok(pattern) => {
+          self.search = Some(pattern);
+          self.search_match = None;
+          self.find_match(true, true);
+        }
+        Err(e) => self.status_message = Some(e),
+      },
+      PromptKind::Command => match ReaderCommand::parse(&input) {
+        Ok(command) => self.run_command(command),
+        Err(e) => self.status_message = Some(e),
+      },
+      PromptKind::Bookmark => {
+        self.book_state.bookmarks.push(Bookmark {
+          name: input,
+          chapter: self.current_chapter_index,
+          scroll: self.scroll_position,
+        });
+        self
+          .book_state
+          .bookmarks
+          .sort_by_key(|bookmark| (bookmark.chapter, bookmark.scroll));
+        self.save_book_state("Bookmark saved");
+      }
+      PromptKind::Annotation => {
+        self.book_state.annotations.push(Annotation {
+          chapter: self.current_chapter_index,
+          scroll: self.scroll_position,
+          note: input,
+        });
+        self
+          .book_state
+          .annotations
+          .sort_by_key(|annotation| (annotation.chapter, annotation.scroll));
+        self.save_book_state("Annotation saved");
+      }
+    }
+  }
+
+  /// Submit the open prompt, act on the selected overlay entry, or confirm quitting
+  pub fn confirm(&mut self) -> Result<(), String> {
+    if self.pending_quit {
+      self.should_quit = true;
+      Ok(())
+    } else if self.prompt.is_some() {
+      self.submit_prompt();
+      Ok(())
+    } else {
+      self.confirm_selection()
+    }
+  }
+
+  /// Close whichever image, prompt or overlay is open, or withdraw a pending quit
+  pub fn cancel(&mut self) {
+    if self.pending_quit {
+      self.pending_quit = false;
+      self.status_message = None;
+    } else if self.image_view.is_some() {
+      self.image_view = None;
+    } else if self.prompt.take().is_none() {
+      self.overlay = None;
+    }
+  }
+
+  pub fn show_overlay(&mut self, kind: OverlayKind) {
+    self.status_message = None;
+    // The table of contents opens on the entry being read
+    let selected = match kind {
+      OverlayKind::Toc => self
+        .epub_handler
+        .toc_entries()
+        .iter()
+        .rposition(|entry| entry.chapter <= self.current_chapter_index)
+        .unwrap_or(0),
+      OverlayKind::Outline => self.current_heading().unwrap_or(0),
+      _ => 0,
+    };
+    self.overlay = Some(Overlay { kind, selected });
+  }
+
+  /// Labels for the entries of the open overlay
+  pub fn overlay_entries(&self) -> Vec<String> {
+    match self.overlay.as_ref().map(|overlay| &overlay.kind) {
+      Some(OverlayKind::Toc) => self
+        .epub_handler
+        .toc_entries()
+        .iter()
+        .map(|entry| format!("{}{}", "  ".repeat(entry.depth), entry.title))
+        .collect(),
+      Some(OverlayKind::Bookmarks) => self
+        .book_state
+        .bookmarks
+        .iter()
+        .map(|bookmark| {
+          format!(
+            "Ch {} / line {}: {}",
+            bookmark.chapter + 1,
+            bookmark.scroll,
+            bookmark.name
+          )
+        })
+        .collect(),
+      Some(OverlayKind::Annotations) => self
+        .book_state
+        .annotations
+        .iter()
+        .map(|annotation| {
+          format!(
+            "Ch {} / line {}: {}",
+            annotation.chapter + 1,
+            annotation.scroll,
+            annotation.note
+          )
+        })
+        .collect(),
+      Some(OverlayKind::Highlights) => self
+        .book_state
+        .highlights
+        .iter()
+        .map(|highlight| {
+          format!(
+            "Ch {} / lines {}-{}",
+            highlight.chapter + 1,
+            highlight.start_line,
+            highlight.end_line
+          )
+        })
+        .collect(),
+      Some(OverlayKind::Diagnostics) => self
+        .resource_errors
+        .iter()
+        .map(|(path, error)| format!("{}: {}", path, error))
+        .collect(),
+      Some(OverlayKind::Footnotes) => self
+        .renderable_chapter
+        .footnotes
+        .iter()
+        .map(|note| note.text.clone())
+        .collect(),
+      Some(OverlayKind::Lookup(_, lines)) => lines.clone(),
+      Some(OverlayKind::Stats) => {
+        let mut lines = self.stats.summary();
+        if let Some(furthest) = self.book_state.furthest_position {
+          lines.push(format!(
+            "Furthest read: chapter {} of {}, {:.0}% through it",
+            furthest.chapter + 1,
+            self.epub_handler.get_chapter_count(),
+            furthest.fraction * 100.0
+          ));
+        }
+        lines
+      }
+      Some(OverlayKind::Outline) => self
+        .outline()
+        .into_iter()
+        .map(|(level, title, _)| format!("{}{}", "  ".repeat(level - 1), title))
+        .collect(),
+      Some(OverlayKind::ImageUnsupported(path)) => {
+        vec![
+          "This terminal can't draw images.".to_string(),
+          format!("Image: {}", path.display()),
+          String::new(),
+          match self.config.fallback_image_viewer.split_whitespace().next() {
+            Some(program) => format!("Press Enter to open it with {}.", program),
+            None => "Set fallback_image_viewer (e.g. `xdg-open %s`) to open images \
+                     outside the terminal."
+              .to_string(),
+          },
+        ]
+      }
+      Some(OverlayKind::ChapterFinder(query)) => self
+        .finder_matches(query)
+        .into_iter()
+        .map(|(title, _)| title.to_string())
+        .collect(),
+      None => Vec::new(),
+    }
+  }
+
+  /// TOC entries matching a fuzzy filter, best first; all of them for an empty filter
+  fn finder_matches(&self, query: &str) -> Vec<(&str, usize)> {
+    let entries = self.epub_handler.toc_entries();
+    if query.is_empty() {
+      return entries
+        .iter()
+        .map(|entry| (entry.title.as_str(), entry.chapter))
+        .collect();
+    }
+
+    let matcher = SkimMatcherV2::default();
+    let mut scored: Vec<(i64, &str, usize)> = entries
+      .iter()
+      .filter_map(|entry| {
+        matcher
+          .fuzzy_match(&entry.title, query)
+          .map(|score| (score, entry.title.as_str(), entry.chapter))
+      })
+      .collect();
+    // Stable sort keeps reading order among equal scores
+    scored.sort_by_key(|&(score, ..)| std::cmp::Reverse(score));
+
+    scored
+      .into_iter()
+      .map(|(_, title, index)| (title, index))
+      .collect()
+  }
+
+  pub fn select_next(&mut self) {
+    let count = self.overlay_entries().len();
+    if let Some(overlay) = &mut self.overlay {
+      overlay.selected = (overlay.selected + 1).min(count.saturating_sub(1));
+    }
+  }
+
+  pub fn select_previous(&mut self) {
+    if let Some(overlay) = &mut self.overlay {
+      overlay.selected = overlay.selected.saturating_sub(1);
+    }
+  }
+
+  fn confirm_selection(&mut self) -> Result<(), String> {
+    let Some(overlay) = self.overlay.take() else {
+      return Ok(());
+    };
+
+    match overlay.kind {
+      OverlayKind::Toc => {
+        if let Some(entry) = self.epub_handler.toc_entries().get(overlay.selected) {
+          let chapter = entry.chapter;
+          match self.epub_handler.toc_anchor(entry) {
+            Some(anchor) => self.jump_to_anchor(chapter, &anchor)?,
+            None => self.jump_to(chapter, 0)?,
+          }
+        }
+      }
+      OverlayKind::Bookmarks => {
+        if let Some(bookmark) = self.book_state.bookmarks.get(overlay.selected) {
+          let (chapter, scroll) = (bookmark.chapter, bookmark.scroll);
+          self.jump_to(chapter, scroll)?;
+        }
+      }
+      OverlayKind::Annotations => {
+        if let Some(annotation) = self.book_state.annotations.get(overlay.selected) {
+          let (chapter, scroll) = (annotation.chapter, annotation.scroll);
+          self.jump_to(chapter, scroll)?;
+        }
+      }
+      OverlayKind::Highlights => {
+        if let Some(highlight) = self.book_state.highlights.get(overlay.selected) {
+          let (chapter, scroll) = (highlight.chapter, highlight.start_line);
+          self.jump_to(chapter, scroll)?;
+        }
+      }
+      OverlayKind::ChapterFinder(query) => {
+        if let Some(&(_, chapter)) = self.finder_matches(&query).get(overlay.selected) {
+          self.jump_to(chapter, 0)?;
+        }
+      }
+      OverlayKind::Outline => {
+        if let Some(&(_, _, line)) = self.outline().get(overlay.selected) {
+          self.scroll_to_line(line);
+        }
+      }
+      // Opening the image needs the terminal, so `take_fallback_image` does it
+      OverlayKind::Diagnostics
+      | OverlayKind::Footnotes
+      | OverlayKind::Lookup(..)
+      | OverlayKind::Stats
+      | OverlayKind::ImageUnsupported(_) => {}
+    }
+    Ok(())
+  }
+
+  /// Remove the selected overlay entry, where that makes sense
+  pub fn delete_selected(&mut self) {
+    let Some(overlay) = &mut self.overlay else {
+      return;
+    };
+
+    match overlay.kind {
+      OverlayKind::Bookmarks => {
+        if overlay.selected < self.book_state.bookmarks.len() {
+          self.book_state.bookmarks.remove(overlay.selected);
+          overlay.selected = overlay
+            .selected
+            .min(self.book_state.bookmarks.len().saturating_sub(1));
+          self.save_book_state("Bookmark removed");
+        }
+      }
+      OverlayKind::Annotations => {
+        if overlay.selected < self.book_state.annotations.len() {
+          self.book_state.annotations.remove(overlay.selected);
+          overlay.selected = overlay
+            .selected
+            .min(self.book_state.annotations.len().saturating_sub(1));
+          self.save_book_state("Annotation removed");
+        }
+      }
+      OverlayKind::Highlights => {
+        if overlay.selected < self.book_state.highlights.len() {
+          self.book_state.highlights.remove(overlay.selected);
+          overlay.selected = overlay
+            .selected
+            .min(self.book_state.highlights.len().saturating_sub(1));
+          self.save_book_state("Highlight removed");
+        }
+      }
+      OverlayKind::Toc
+      | OverlayKind::Diagnostics
+      | OverlayKind::Footnotes
+      | OverlayKind::Lookup(..)
+      | OverlayKind::Stats
+      | OverlayKind::Outline
+      | OverlayKind::ImageUnsupported(_)
+      | OverlayKind::ChapterFinder(_) => {}
+    }
+  }
+
+  /// Start a highlight at the current line, or finish the one in progress
+  pub fn toggle_highlight(&mut self) {
+    match self.highlight_start.take() {
+      None => {
+        self.highlight_start = Some(self.scroll_position);
+        self.status_message = Some(format!(
+          "Highlight starts at line {}, press v again to finish",
+          self.scroll_position
+        ));
+      }
+      Some(start) => {
+        self.book_state.highlights.push(Highlight {
+          chapter: self.current_chapter_index,
+          start_line: start.min(self.scroll_position),
+          end_line: start.max(self.scroll_position),
+        });
+        self
+          .book_state
+          .highlights
+          .sort_by_key(|highlight| (highlight.chapter, highlight.start_line));
+        self.save_book_state("Highlight saved");
+      }
+    }
+  }
+
+  /// Move the word cursor to the next or previous word, starting on the reading line
+  pub fn move_word_cursor(&mut self, forward: bool) {
+    let lines = &self.chapter_layout.line_texts;
+    let word_count = |line: usize| {
+      lines
+        .get(line)
+        .map_or(0, |text| text.split_whitespace().count())
+    };
+
+    let next = match self.word_cursor {
+      None => (self.scroll_position..lines.len())
+        .find(|&line| word_count(line) > 0)
+        .map(|line| (line, 0)),
+      Some((line, word)) if forward => {
+        if word + 1 < word_count(line) {
+          Some((line, word + 1))
+        } else {
+          (line + 1..lines.len())
+            .find(|&line| word_count(line) > 0)
+            .map(|line| (line, 0))
+        }
+      }
+      Some((line, word)) => {
+        if word > 0 {
+          Some((line, word - 1))
+        } else {
+          (0..line)
+            .rev()
+            .find(|&line| word_count(line) > 0)
+            .map(|line| (line, word_count(line) - 1))
+        }
+      }
+    };
+
+    let Some((line, word)) = next else {
+      return;
+    };
+    self.word_cursor = Some((line, word));
+    self.scroll_into_view(line);
+  }
+
+  /// Scroll to the next (or previous) match of the active search, wrapping
+  /// around the chapter; `inclusive` counts matches on the top line itself
+  pub fn find_match(&mut self, forward: bool, inclusive: bool) {
+    let Some(search) = &self.search else {
+      return;
+    };
+    let current = self.scroll_position;
+
+    // Every match as (line, column), in reading order
+    let matches: Vec<(usize, usize)> = self
+      .chapter_layout
+      .line_texts
+      .iter()
+      .enumerate()
+      .flat_map(|(line, text)| {
+        search
+          .find_all(text)
+          .into_iter()
+          .map(move |(start, _)| (line, start))
+      })
+      .collect();
+    if matches.is_empty() {
+      self.status_message = Some("Pattern not found in this chapter".to_string());
+      return;
+    }
+
+    // Step from the last match visited while it is still on screen, otherwise
+    // from the top line; out-of-range targets wrap around
+    let count = matches.len() as isize;
+    let visited = self
+      .search_match
+      .filter(|&index| matches.get(index).is_some_and(|&(line, _)| line == current));
+    let target = match (visited, forward) {
+      (Some(index), true) => index as isize + 1,
+      (Some(index), false) => index as isize - 1,
+      (None, true) => matches
+        .partition_point(|&(line, _)| line < current || (!inclusive && line == current))
+        as isize,
+      (None, false) => matches.partition_point(|&(line, _)| line < current) as isize - 1,
+    };
+    let index = target.rem_euclid(count) as usize;
+
+    self.search_match = Some(index);
+    self.scroll_to_line(matches[index].0);
+    self.status_message = if target == index as isize {
+      None
+    } else {
+      Some("Search wrapped".to_string())
+    };
+  }
+
+  /// Step through the formatted view, the parsed blocks and the raw HTML
+  pub fn cycle_debug_view(&mut self) {
+    self.set_debug_view(match self.debug_view {
+      None => Some(DebugView::Blocks),
+      Some(DebugView::Blocks) => Some(DebugView::Html),
+      Some(DebugView::Html) => None,
+    });
+  }
+
+  pub fn set_debug_view(&mut self, view: Option<DebugView>) {
+    self.debug_view = view;
+    self.scroll_position = 0;
+    self.refresh_debug_text();
+    self.content_version += 1;
+  }
+
+  fn refresh_debug_text(&mut self) {
+    self.debug_text = match self.debug_view {
+      None => Vec::new(),
+      Some(DebugView::Blocks) => self
+        .renderable_chapter
+        .blocks
+        .iter()
+        .map(|block| format!("{:?}", block))
+        .collect(),
+      Some(DebugView::Html) => {
+        let mut lines = Vec::new();
+        for index in self.chapter_range() {
+          match self.epub_handler.get_chapter_content_raw(index) {
+            Ok(html) => lines.extend(html.lines().map(str::to_string)),
+            Err(e) => lines.push(e),
+          }
+        }
+        lines
+      }
+    };
+  }
+
+  /// Changes whenever the chapter's layout has to be redone
+  pub fn content_version(&self) -> u64 {
+    self.content_version
+  }
+
+  /// Whether the code blocks in view can scroll further that way; at either
+  /// end the sideways keys go back to turning chapters
+  pub fn can_scroll_code(&self, right: bool) -> bool {
+    if right {
+      self.code_scroll < self.visible_code_overflow()
+    } else {
+      self.code_scroll > 0
+    }
+  }
+
+  /// Forget the sideways scroll once no code is in view, so the next code
+  /// block starts at its left edge
+  pub fn reset_code_scroll_out_of_view(&mut self) {
+    if self.visible_code_lines().next().is_none() {
+      self.code_scroll = 0;
+    }
+  }
+
+  /// Scroll the code blocks in view sideways, no further than their widest
+  /// line needs
+  pub fn scroll_code(&mut self, right: bool) {
+    const STEP: usize = 8;
+    self.code_scroll = if right {
+      (self.code_scroll + STEP).min(self.visible_code_overflow())
+    } else {
+      self.code_scroll.saturating_sub(STEP)
+    };
+  }
+
+  // How many columns the widest code line in view is wider than the screen
+  fn visible_code_overflow(&self) -> usize {
+    self
+      .visible_code_lines()
+      .map(|&(_, overflow)| overflow)
+      .max()
+      .unwrap_or(0)
+  }
+
+  fn visible_code_lines(&self) -> impl Iterator<Item = &(usize, usize)> {
+    let layout = &self.chapter_layout;
+    let visible = self.scroll_position..self.scroll_position + layout.viewport_height;
+    layout
+      .code_lines
+      .iter()
+      .filter(move |(line, _)| visible.contains(line))
+  }
+
+  pub fn toggle_line_numbers(&mut self) {
+    self.show_line_numbers = !self.show_line_numbers;
+    self.book_state.preferences.line_numbers = Some(self.show_line_numbers);
+    self.save_book_state(if self.show_line_numbers {
+      "Line numbers shown"
+    } else {
+      "Line numbers hidden"
+    });
+  }
+
+  /// Go from both bars to the footer alone, to neither, and back
+  pub fn cycle_bars(&mut self) {
+    let (message, header, footer) = match (self.show_header, self.show_footer) {
+      (true, true) => ("Header hidden", false, true),
+      (false, true) => ("Header and footer hidden", false, false),
+      _ => ("Header and footer shown", true, true),
+    };
+    self.show_header = header;
+    self.show_footer = footer;
+    self.status_message = Some(message.to_string());
+  }
+
+  /// Hide or show image placeholders, along with the blank lines around them
+  pub fn toggle_images(&mut self) {
+    self.show_images = !self.show_images;
+    self.content_version += 1;
+    self.book_state.preferences.show_images = Some(self.show_images);
+    self.save_book_state(if self.show_images {
+      "Images shown"
+    } else {
+      "Images hidden"
+    });
+  }
+
+  /// Move the link selection to the next (or previous) link, wrapping around
+  pub fn select_link(&mut self, forward: bool) {
+    let links = &self.chapter_layout.links;
+    if links.is_empty() {
+      self.status_message = Some("No links in this chapter".to_string());
+      return;
+    }
+
+    let count = links.len();
+    let height = self.chapter_layout.viewport_height.max(1);
+    let index = match (self.selected_link, forward) {
+      (Some(index), true) => (index + 1) % count,
+      (Some(index), false) => (index + count - 1) % count,
+      // Start from the links on screen
+      (None, true) => links
+        .iter()
+        .position(|link| link.line >= self.scroll_position)
+        .unwrap_or(0),
+      (None, false) => links
+        .iter()
+        .rposition(|link| link.line < self.scroll_position + height)
+        .unwrap_or(count - 1),
+    };
+    let line = links[index].line;
+    self.selected_link = Some(index);
+    self.scroll_into_view(line);
+  }
+
+  /// Scroll just enough to show a line with the configured margin around it
+  fn scroll_into_view(&mut self, line: usize) {
+    let height = self.chapter_layout.viewport_height.max(1);
+    let margin = self.scroll_margin();
+    if line < self.scroll_position + margin {
+      self.scroll_position = line.saturating_sub(margin);
+    } else if line + margin >= self.scroll_position + height {
+      self.scroll_position = line + margin + 1 - height;
+    }
+  }
+
+  /// Scroll a line to the top of the screen, below the configured margin
+  fn scroll_to_line(&mut self, line: usize) {
+    self.scroll_position = line.saturating_sub(self.scroll_margin());
+  }
+
+  // The configured margin, leaving room for the line itself on small screens
+  fn scroll_margin(&self) -> usize {
+    let height = self.chapter_layout.viewport_height.max(1);
+    self.config.scroll_margin.min((height - 1) / 2)
+  }
+
+  /// Go to the target of the selected link
+  pub fn follow_link(&mut self) -> Result<(), String> {
+    let Some(href) = self
+      .selected_link
+      .and_then(|index| self.chapter_layout.links.get(index))
+      .and_then(|link| self.renderable_chapter.links.get(link.link))
+      .cloned()
+    else {
+      return Ok(());
+    };
+
+    if self.renderable_chapter.anchors.contains_key(&href) {
+      return self.jump_to_anchor(self.current_chapter_index, &href);
+    }
+
+    match self.epub_handler.link_target(&href) {
+      Some(chapter) if self.chapter_range().contains(&chapter) => {}
+      Some(chapter) => self.jump_to_anchor(chapter, &href)?,
+      None => self.status_message = Some(format!("External link: {}", href)),
+    }
+    Ok(())
+  }
+
+  /// Go to an element of a spine item, given as a resolved `path#id` link
+  fn jump_to_anchor(&mut self, chapter: usize, anchor: &str) -> Result<(), String> {
+    // A fragment of the chapter on screen is only a scroll away, with no reload
+    if let Some(&block) = self.renderable_chapter.anchors.get(anchor) {
+      if let Some(&line) = self.chapter_layout.block_offsets.get(block) {
+        self.scroll_to_line(line);
+      }
+      return Ok(());
+    }
+
+    self.jump_to(chapter, 0)?;
+    // Land on the fragment once the chapter has been laid out
+    self.pending_anchor = self.renderable_chapter.anchors.get(anchor).copied();
+    Ok(())
+  }
+
+  /// The word under the cursor, or the last word of the reading line
+  fn selected_word(&self) -> Option<String> {
+    let lines = &self.chapter_layout.line_texts;
+    let word = match self.word_cursor {
+      Some((line, word)) => lines.get(line)?.split_whitespace().nth(word)?,
+      None => lines.get(self.scroll_position)?.split_whitespace().last()?,
+    };
+
+    let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+    (!word.is_empty()).then(|| word.to_string())
+  }
+
+  /// Run the configured dictionary command on the selected word in the
+  /// background; its output is shown once `receive_lookup` picks it up
+  pub fn lookup_word(&mut self) {
+    let Some(word) = self.selected_word() else {
+      self.status_message = Some("No word to look up".to_string());
+      return;
+    };
+
+    let mut parts = self.config.dictionary_command.split_whitespace();
+    let Some(program) = parts.next().map(str::to_string) else {
+      self.status_message = Some("No dictionary command configured".to_string());
+      return;
+    };
+    let mut args: Vec<String> = parts.map(|part| part.replace("%s", &word)).collect();
+    // Without a `%s` the word goes last, as most dictionary commands expect
+    if !self.config.dictionary_command.contains("%s") {
+      args.push(word.clone());
+    }
+
+    // A slow dictionary mustn't freeze the reader, so it runs on its own thread
+    let (result_tx, result_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+      let result = match Command::new(&program).args(&args).output() {
+        Ok(output) => {
+          let text = if output.stdout.is_empty() {
+            String::from_utf8_lossy(&output.stderr).into_owned()
+          } else {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+          };
+          Ok(text.lines().map(|line| line.to_string()).collect())
+        }
+        Err(e) => Err(format!("Failed to run {}: {}", program, e)),
+      };
+      let _ = result_tx.send(result);
+    });
+
+    self.status_message = Some(format!("Looking up {}…", word));
+    self.lookup = Some((word, result_rx));
   }
 
-  pub fn page_down(&mut self, page_size: usize) {
-    self.scroll_position = self.scroll_position.saturating_add(page_size);
+  /// Show the dictionary's answer once the lookup has finished
+  pub fn receive_lookup(&mut self) {
+    let Some((word, results)) = &self.lookup else {
+      return;
+    };
+    let result = match results.try_recv() {
+      Ok(result) => result,
+      Err(mpsc::TryRecvError::Empty) => return,
+      Err(mpsc::TryRecvError::Disconnected) => Err(format!("Failed to look up {}", word)),
+    };
+    let Some((word, _)) = self.lookup.take() else {
+      return;
+    };
+
+    match result {
+      Ok(lines) => {
+        self.status_message = None;
+        self.show_overlay(OverlayKind::Lookup(word, lines));
+      }
+      Err(e) => self.status_message = Some(e),
+    }
+  }
+
+  /// Whether the theme was changed since last asked, so the palette has to be
+  /// chosen again
+  pub fn take_theme_change(&mut self) -> bool {
+    std::mem::take(&mut self.theme_changed)
+  }
+
+  /// Close the unsupported-image notice and hand back its image, when there is
+  /// a fallback viewer to open it with
+  pub fn take_fallback_image(&mut self) -> Option<PathBuf> {
+    if self.config.fallback_image_viewer.is_empty() {
+      return None;
+    }
+    match self.overlay.take() {
+      Some(Overlay {
+        kind: OverlayKind::ImageUnsupported(path),
+        ..
+      }) => Some(path),
+      overlay => {
+        self.overlay = overlay;
+        None
+      }
+    }
+  }
+
+  /// Open an image with an external viewer command and wait for it to exit
+  pub fn open_in_viewer(&mut self, viewer: &str, image_path: &Path) {
+    let mut parts = viewer.split_whitespace();
+    let Some(program) = parts.next() else {
+      self.status_message = Some("No image viewer configured".to_string());
+      return;
+    };
+    let path = image_path.to_string_lossy();
+    let args: Vec<String> = parts.map(|part| part.replace("%s", &path)).collect();
+
+    match Command::new(program).args(&args).status() {
+      Ok(status) if !status.success() => {
+        self.status_message = Some(format!("{} exited with {}", program, status));
+      }
+      Ok(_) => {}
+      Err(e) => {
+        self.status_message = Some(format!("Failed to run {}: {}", program, e));
+      }
+    }
+  }
+
+  /// Read the chapter aloud from the reading position with the configured TTS command
+  pub fn speak_from_here(&mut self) {
+    self.stop_speaking();
+
+    let start_block = self.chapter_layout.block_at_line(self.scroll_position);
+    let text = self.renderable_chapter.plain_text_from(start_block);
+
+    let mut parts = self.config.tts_command.split_whitespace();
+    let Some(program) = parts.next() else {
+      self.status_message = Some("No text-to-speech command configured".to_string());
+      return;
+    };
+
+    let spawned = Command::new(program)
+      .args(parts)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .spawn();
+
+    match spawned {
+      Ok(mut child) => {
+        // Feed the text from a thread so a slow reader never blocks the UI
+        if let Some(mut stdin) = child.stdin.take() {
+          std::thread::spawn(move || {
+            let _ = stdin.write_all(text.as_bytes());
+          });
+        }
+        self.tts_process = Some(child);
+        self.status_message = Some("Speaking (S to stop)".to_string());
+      }
+      Err(e) => {
+        self.status_message = Some(format!("Failed to run {}: {}", program, e));
+      }
+    }
+  }
+
+  pub fn stop_speaking(&mut self) {
+    if let Some(mut child) = self.tts_process.take() {
+      let _ = child.kill();
+      let _ = child.wait();
+      self.status_message = None;
+    }
+  }
+
+  /// Reap the text-to-speech command once it has finished on its own
+  pub fn reap_speech(&mut self) {
+    if let Some(child) = &mut self.tts_process
+      && !matches!(child.try_wait(), Ok(None))
+    {
+      self.tts_process = None;
+    }
+  }
+
+  /// Whether a content line of the current chapter is highlighted or being selected
+  pub fn is_line_highlighted(&self, line: usize) -> bool {
+    let pending = self.highlight_start.is_some_and(|start| {
+      (start.min(self.scroll_position)..=start.max(self.scroll_position)).contains(&line)
+    });
+
+    pending
+      || self
+        .book_state
+        .highlights
+        .iter()
+        .any(|highlight| highlight.contains(self.current_chapter_index, line))
+  }
+
+  /// Dimensions and size of the current chapter's images, by source, where known
+  pub fn image_sizes(&self) -> HashMap<String, String> {
+    self
+      .image_paths
+      .iter()
+      .zip(&self.extracted_images)
+      .filter_map(|(src, path)| Some((src.clone(), self.image_info.get(path)?.clone()?)))
+      .collect()
+  }
+
+  /// Headings of the current chapter as (level, text, content line), using
+  /// the block offsets of the last layout
+  pub fn outline(&self) -> Vec<(usize, String, usize)> {
+    self
+      .renderable_chapter
+      .blocks
+      .iter()
+      .enumerate()
+      .filter_map(|(index, block)| match block {
+        RenderableBlock::Heading(level, runs) => {
+          let line = self.chapter_layout.block_offsets.get(index).copied();
+          Some((*level, runs_text(runs), line.unwrap_or(0)))
+        }
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Index in the outline of the heading whose section is being read
+  pub fn current_heading(&self) -> Option<usize> {
+    self
+      .outline()
+      .iter()
+      .rposition(|&(_, _, line)| line <= self.scroll_position)
+  }
+
+  /// Scroll to the next or previous heading of the chapter; past the last or
+  /// first one, stop or turn the chapter as configured
+  pub fn jump_to_heading(&mut self, forward: bool) -> Result<(), String> {
+    // A heading scrolled to sits below the margin, so measure from there
+    let top = self.scroll_position + self.scroll_margin();
+    let lines = self.outline().into_iter().map(|(_, _, line)| line);
+    let target = if forward {
+      lines.filter(|&line| line > top).min()
+    } else {
+      lines.filter(|&line| line < top).max()
+    };
+
+    match target {
+      Some(line) => self.scroll_to_line(line),
+      None if self.config.heading_jumps_turn_chapters && forward => self.next_chapter()?,
+      None if self.config.heading_jumps_turn_chapters => self.previous_chapter()?,
+      None => {
+        self.status_message = Some(if forward {
+          "No more headings in this chapter".to_string()
+        } else {
+          "No earlier headings in this chapter".to_string()
+        });
+      }
+    }
+    Ok(())
+  }
+
+  pub fn toggle_outline(&mut self) {
+    self.show_outline = !self.show_outline;
+  }
+
+  /// Scroll positions of annotations in the current chapter
+  pub fn annotated_lines(&self) -> Vec<usize> {
+    self
+      .book_state
+      .annotations
+      .iter()
+      .filter(|annotation| annotation.chapter == self.current_chapter_index)
+      .map(|annotation| annotation.scroll)
+      .collect()
   }
 
-  pub fn page_up(&mut self, page_size: usize) {
-    self.scroll_position = self.scroll_position.saturating_sub(page_size);
+  /// Remember the reading position for the next start; kept apart from bookmarks
+  pub fn save_last_position(&mut self) -> Result<(), String> {
+    self.book_state.last_position = Some(self.current_position());
+    self.note_furthest();
+    let saved = self.book_state.save(&self.epub_handler.base_path);
+
+    let base_path = &self.epub_handler.base_path;
+    let recorded = RecentBooks::record(RecentBook {
+      path: std::fs::canonicalize(base_path).unwrap_or_else(|_| base_path.clone()),
+      title: self.epub_handler.book_title(),
+      progress: self.get_chapter_progress(),
+      last_read: chrono::Local::now().timestamp(),
+    });
+    saved.and(recorded)
+  }
+
+  /// Append this session's statistics to the book's log, when enabled
+  pub fn log_session_stats(&self) -> Result<(), String> {
+    if !self.config.stats_log {
+      return Ok(());
+    }
+    append_stats_log(&self.epub_handler.base_path, &self.stats.log_line())
+  }
+
+  fn save_book_state(&mut self, success_message: &str) {
+    self.status_message = Some(match self.book_state.save(&self.epub_handler.base_path) {
+      Ok(()) => success_message.to_string(),
+      Err(e) => e,
+    });
+  }
+
+  /// Whether images can be viewed at all, in the terminal or an external viewer
+  pub fn can_show_images(&self) -> bool {
+    self.image_picker.is_some() || !self.config.image_viewer.is_empty()
+  }
+
+  /// Show an image over the whole screen, with the terminal's image protocol
+  pub fn show_image(&mut self, path: PathBuf) {
+    let Some(picker) = &self.image_picker else {
+      return;
+    };
+    let image = load_image(picker, &path);
+    self.image_view = Some(ImageView { path, image });
   }
 
   pub fn get_current_image_path(&self) -> Option<&PathBuf> {
     self.extracted_images.get(self.current_image_index)
   }
 
+  /// Whether the current chapter should be laid out right-to-left
+  pub fn is_rtl(&self) -> bool {
+    match self.config.text_direction {
+      TextDirection::Ltr => false,
+      TextDirection::Rtl => true,
+      TextDirection::Auto => self.renderable_chapter.rtl || self.epub_handler.is_rtl_language(),
+    }
+  }
+
+  /// Whether keys pointing right go to the next chapter, going by the
+  /// configured direction or else the book's page progression
+  pub fn right_turns_forward(&self) -> bool {
+    let direction = match self.config.page_progression {
+      TextDirection::Auto => self.config.text_direction,
+      direction => direction,
+    };
+    let rtl = match direction {
+      TextDirection::Ltr => Some(false),
+      TextDirection::Rtl => Some(true),
+      TextDirection::Auto => None,
+    };
+    self.epub_handler.right_turns_forward(rtl)
+  }
+
   pub fn get_chapter_title(&self) -> String {
-    // For now, we'll just return a generic title
-    // In a more complete implementation, we would extract the actual chapter title
-    format!("Chapter {}", self.current_chapter_index + 1)
+    match self.epub_handler.chapter_title(self.current_chapter_index) {
+      Some(title) => title.to_string(),
+      None => match &self.renderable_chapter.title {
+        Some(title) => title.clone(),
+        None => format!("Chapter {}", self.current_chapter_index + 1),
+      },
+    }
   }
 
   pub fn get_chapter_progress(&self) -> f64 {
+    match self.config.progress_mode {
+      ProgressMode::Spine => self.get_spine_progress(),
+      ProgressMode::Length => self
+        .get_length_progress()
+        .unwrap_or_else(|| self.get_spine_progress()),
+    }
+  }
+
+  /// The reading position as a locator into the spine item at the top of the screen
+  pub fn locator(&self) -> Locator {
+    // Before the first render only the resumed fraction is known
+    let mut offset = match self.pending_scroll {
+      Some(fraction) => (self.renderable_chapter.text_len() as f64 * fraction) as usize,
+      None => {
+        let block = self.chapter_layout.block_at_line(self.scroll_position);
+        self.renderable_chapter.text_len_before(block)
+      }
+    };
+
+    let mut index = self.current_chapter_index;
+    for (position, &(item, length)) in self.item_lengths.iter().enumerate() {
+      index = item;
+      if offset < length || position + 1 == self.item_lengths.len() {
+        break;
+      }
+      offset -= length;
+    }
+
+    Locator {
+      path: self.epub_handler.spine_path(index).unwrap_or_default(),
+      offset,
+    }
+  }
+
+  /// How far the current chapter has been scrolled, from 0 to 1
+  pub fn chapter_fraction(&self) -> f64 {
+    match self.chapter_layout.total_lines {
+      0 => 0.0,
+      lines => (self.scroll_position as f64 / lines as f64).min(1.0),
+    }
+  }
+
+  /// Estimated minutes of reading left in the book, once chapter lengths are known
+  pub fn minutes_left(&self) -> Option<f64> {
+    let words = self.epub_handler.get_chapter_word_counts();
+    if words.is_empty() {
+      return None;
+    }
+
+    let range = self.chapter_range();
+    let current: usize = words.get(range.clone()).map_or(0, |w| w.iter().sum());
+    let after: usize = words.iter().skip(range.end).sum();
+    let words_left = after as f64 + current as f64 * (1.0 - self.chapter_fraction());
+    Some(words_left / self.config.words_per_minute.max(1) as f64)
+  }
+
+  fn get_spine_progress(&self) -> f64 {
     if self.epub_handler.get_chapter_count() <= 1 {
       1.0
     } else {
       self.current_chapter_index as f64 / (self.epub_handler.get_chapter_count() - 1) as f64
     }
   }
+
+  /// Progress weighted by chapter text length, including the position inside the current chapter
+  fn get_length_progress(&self) -> Option<f64> {
+    let lengths = self.epub_handler.get_chapter_lengths();
+    let total: usize = lengths.iter().sum();
+    if total == 0 {
+      return None;
+    }
+
+    let range = self.chapter_range();
+    let read: usize = lengths.iter().take(range.start).sum();
+    let current: usize = lengths.get(range).map_or(0, |l| l.iter().sum());
+
+    Some((read as f64 + current as f64 * self.chapter_fraction()) / total as f64)
+  }
+}
+
+impl Drop for AppState {
+  // However the reader exits, speech stops with it
+  fn drop(&mut self) {
+    self.stop_speaking();
+  }
+}
+
+/// Length-weighted progress and reading-time estimates need every chapter's length
+fn needs_chapter_lengths(config: &Config) -> bool {
+  config.progress_mode == ProgressMode::Length || config.footer_format.contains("{time_left}")
+}
+
+fn process_options(config: &Config) -> ProcessOptions {
+  ProcessOptions {
+    smart_punctuation: config.smart_punctuation,
+  }
 }
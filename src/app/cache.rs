@@ -0,0 +1,53 @@
+use crate::epub::content::RenderableChapter;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// A processed spine item, with its images already extracted
+pub struct SpineItem {
+  pub chapter: RenderableChapter,
+  pub image_paths: Vec<String>,
+  pub images: Vec<Result<PathBuf, String>>, // Extracted file or error, per image path
+}
+
+/// Recently processed spine items, most recently used first
+///
+/// Book content never changes while reading, so entries are only ever evicted
+/// for space, never invalidated; reloading the book starts a new cache.
+pub struct ChapterCache {
+  capacity: usize,
+  entries: VecDeque<(usize, Rc<SpineItem>)>,
+}
+
+impl ChapterCache {
+  pub fn new(capacity: usize) -> Self {
+    ChapterCache {
+      capacity,
+      entries: VecDeque::with_capacity(capacity),
+    }
+  }
+
+  pub fn contains(&self, index: usize) -> bool {
+    self.entries.iter().any(|(cached, _)| *cached == index)
+  }
+
+  pub fn get(&mut self, index: usize) -> Option<Rc<SpineItem>> {
+    let position = self
+      .entries
+      .iter()
+      .position(|(cached, _)| *cached == index)?;
+    let entry = self.entries.remove(position)?;
+    let item = Rc::clone(&entry.1);
+    self.entries.push_front(entry);
+    Some(item)
+  }
+
+  pub fn insert(&mut self, index: usize, item: Rc<SpineItem>) {
+    if self.capacity == 0 {
+      return;
+    }
+    self.entries.retain(|(cached, _)| *cached != index);
+    self.entries.truncate(self.capacity - 1);
+    self.entries.push_front((index, item));
+  }
+}
@@ -0,0 +1,61 @@
+/// A line of text being typed, with a cursor that can move within it
+#[derive(Debug, Default)]
+pub struct TextInput {
+  text: String,
+  cursor: usize, // Byte offset, always on a character boundary
+}
+
+/// Where a cursor key moves the cursor
+#[derive(Debug, Clone, Copy)]
+pub enum CursorMove {
+  Left,
+  Right,
+  Home,
+  End,
+}
+
+impl TextInput {
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  /// The text split at the cursor
+  pub fn split(&self) -> (&str, &str) {
+    self.text.split_at(self.cursor)
+  }
+
+  pub fn insert(&mut self, c: char) {
+    self.text.insert(self.cursor, c);
+    self.cursor += c.len_utf8();
+  }
+
+  /// Insert pasted text, joining its lines since the input holds only one
+  pub fn paste(&mut self, text: &str) {
+    for c in text.chars().filter(|&c| c != '\r') {
+      self.insert(if c == '\n' { ' ' } else { c });
+    }
+  }
+
+  /// Remove the character before the cursor
+  pub fn backspace(&mut self) {
+    if let Some(c) = self.text[..self.cursor].chars().next_back() {
+      self.cursor -= c.len_utf8();
+      self.text.remove(self.cursor);
+    }
+  }
+
+  pub fn move_cursor(&mut self, to: CursorMove) {
+    self.cursor = match to {
+      CursorMove::Left => self.text[..self.cursor]
+        .chars()
+        .next_back()
+        .map_or(0, |c| self.cursor - c.len_utf8()),
+      CursorMove::Right => self.text[self.cursor..]
+        .chars()
+        .next()
+        .map_or(self.text.len(), |c| self.cursor + c.len_utf8()),
+      CursorMove::Home => 0,
+      CursorMove::End => self.text.len(),
+    };
+  }
+}
@@ -0,0 +1,54 @@
+use crate::ui::UserAction;
+use ratatui::crossterm::event::KeyCode;
+use std::collections::HashMap;
+
+/// Reading-view keys rebound in the config file's `[keys]` table, which take
+/// precedence over the built-in bindings
+///
+/// Only reading keys can be rebound, so text typed into a prompt always goes
+/// into the prompt.
+#[derive(Debug, Default)]
+pub struct Keymap {
+  bindings: HashMap<KeyCode, UserAction>,
+}
+
+impl Keymap {
+  /// Build the keymap from key names such as `space`, `tab` or `J`
+  pub fn new(keys: &HashMap<String, UserAction>) -> Result<Self, String> {
+    let bindings = keys
+      .iter()
+      .map(|(name, action)| {
+        let key = parse_key(name).ok_or_else(|| format!("Unknown key {:?} in [keys]", name))?;
+        Ok((key, action.clone()))
+      })
+      .collect::<Result<_, String>>()?;
+    Ok(Keymap { bindings })
+  }
+
+  pub fn get(&self, key: KeyCode) -> Option<UserAction> {
+    self.bindings.get(&key).cloned()
+  }
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+  let mut chars = name.chars();
+  if let (Some(c), None) = (chars.next(), chars.next()) {
+    return Some(KeyCode::Char(c));
+  }
+  Some(match name.to_lowercase().as_str() {
+    "space" => KeyCode::Char(' '),
+    "tab" => KeyCode::Tab,
+    "enter" => KeyCode::Enter,
+    "esc" => KeyCode::Esc,
+    "backspace" => KeyCode::Backspace,
+    "up" => KeyCode::Up,
+    "down" => KeyCode::Down,
+    "left" => KeyCode::Left,
+    "right" => KeyCode::Right,
+    "pageup" => KeyCode::PageUp,
+    "pagedown" => KeyCode::PageDown,
+    "home" => KeyCode::Home,
+    "end" => KeyCode::End,
+    _ => return None,
+  })
+}
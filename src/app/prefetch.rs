@@ -0,0 +1,56 @@
+use crate::epub::content::RenderableChapter;
+use crate::epub::processor::{ProcessOptions, process_chapter_html};
+use crate::epub::stylesheet::Stylesheet;
+use std::collections::HashSet;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// Processes chapter HTML on a background thread
+///
+/// `EpubDoc` can't leave the main thread, so the raw HTML is read there and only
+/// the parsing happens here. Results are picked up with `try_recv`.
+pub struct Prefetcher {
+  requests: Sender<(usize, String)>,
+  results: Receiver<(usize, RenderableChapter)>,
+  pending: HashSet<usize>,
+}
+
+impl Prefetcher {
+  pub fn new(stylesheet: Stylesheet, options: ProcessOptions) -> Self {
+    let (requests, request_rx) = mpsc::channel::<(usize, String)>();
+    let (result_tx, results) = mpsc::channel();
+
+    // The worker exits once the prefetcher (and so the request sender) is dropped
+    thread::spawn(move || {
+      for (index, raw_html) in request_rx {
+        let chapter = process_chapter_html(&raw_html, &stylesheet, &options);
+        if result_tx.send((index, chapter)).is_err() {
+          break;
+        }
+      }
+    });
+
+    Prefetcher {
+      requests,
+      results,
+      pending: HashSet::new(),
+    }
+  }
+
+  pub fn is_pending(&self, index: usize) -> bool {
+    self.pending.contains(&index)
+  }
+
+  pub fn request(&mut self, index: usize, raw_html: String) {
+    if self.requests.send((index, raw_html)).is_ok() {
+      self.pending.insert(index);
+    }
+  }
+
+  /// A finished chapter, if one is ready
+  pub fn try_recv(&mut self) -> Option<(usize, RenderableChapter)> {
+    let (index, chapter) = self.results.try_recv().ok()?;
+    self.pending.remove(&index);
+    Some((index, chapter))
+  }
+}
@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+
+/// What the reader did since the book was opened
+pub struct SessionStats {
+  started: Instant,
+  pub chapters_advanced: usize,
+  pub lines_scrolled: usize,
+}
+
+impl SessionStats {
+  pub fn new() -> Self {
+    SessionStats {
+      started: Instant::now(),
+      chapters_advanced: 0,
+      lines_scrolled: 0,
+    }
+  }
+
+  pub fn elapsed(&self) -> Duration {
+    self.started.elapsed()
+  }
+
+  /// Lines for the statistics overlay
+  pub fn summary(&self) -> Vec<String> {
+    vec![
+      format!("Reading for {}", format_duration(self.elapsed())),
+      format!("Chapters advanced: {}", self.chapters_advanced),
+      format!("Lines scrolled: {}", self.lines_scrolled),
+    ]
+  }
+
+  /// A tab-separated line for the book's statistics log
+  pub fn log_line(&self) -> String {
+    format!(
+      "{}\t{}s\t{} chapters\t{} lines",
+      chrono::Local::now().format("%Y-%m-%d %H:%M"),
+      self.elapsed().as_secs(),
+      self.chapters_advanced,
+      self.lines_scrolled
+    )
+  }
+}
+
+fn format_duration(duration: Duration) -> String {
+  let minutes = duration.as_secs() / 60;
+  if minutes < 60 {
+    format!("{} min", minutes)
+  } else {
+    format!("{} h {} min", minutes / 60, minutes % 60)
+  }
+}
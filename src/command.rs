@@ -0,0 +1,59 @@
+use crate::config::Theme;
+
+/// A command typed at the `:` prompt
+pub enum Command {
+  /// Jump to a percentage of the whole book
+  GoToPercent(f64),
+  /// Indent paragraphs of this book by a number of spaces (`indent 2`)
+  ParagraphIndent(usize),
+  /// Draw this book in a color scheme (`theme light`)
+  Theme(Theme),
+  /// Keep the text of this book within a number of columns (`width 72`, or
+  /// `width 0` for the whole screen)
+  MaxWidth(usize),
+}
+
+impl Command {
+  pub fn parse(input: &str) -> Result<Self, String> {
+    let input = input.trim();
+
+    if let Some(percent) = input.strip_suffix('%') {
+      let percent: f64 = percent
+        .trim()
+        .parse()
+        .map_err(|_| format!("Not a percentage: {}", input))?;
+      if !percent.is_finite() {
+        return Err(format!("Not a percentage: {}", input));
+      }
+      return Ok(Command::GoToPercent(percent.clamp(0.0, 100.0)));
+    }
+
+    if let Some(indent) = input.strip_prefix("indent") {
+      let indent = indent
+        .trim()
+        .parse()
+        .map_err(|_| format!("Not a number of spaces: {}", indent.trim()))?;
+      return Ok(Command::ParagraphIndent(indent));
+    }
+
+    if let Some(theme) = input.strip_prefix("theme") {
+      let theme = match theme.trim() {
+        "auto" => Theme::Auto,
+        "dark" => Theme::Dark,
+        "light" => Theme::Light,
+        other => return Err(format!("Not a theme: {} (auto, dark or light)", other)),
+      };
+      return Ok(Command::Theme(theme));
+    }
+
+    if let Some(width) = input.strip_prefix("width") {
+      let width = width
+        .trim()
+        .parse()
+        .map_err(|_| format!("Not a number of columns: {}", width.trim()))?;
+      return Ok(Command::MaxWidth(width));
+    }
+
+    Err(format!("Unknown command: {}", input))
+  }
+}
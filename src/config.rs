@@ -0,0 +1,225 @@
+use crate::parser::CliArgs;
+use crate::ui::UserAction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// How overall reading progress is computed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProgressMode {
+  /// Every spine item counts the same
+  #[default]
+  Spine,
+  /// Chapters are weighted by their text length
+  Length,
+}
+
+/// Writing direction used for layout and chapter navigation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextDirection {
+  /// Follow the book's markup and metadata
+  #[default]
+  Auto,
+  Ltr,
+  Rtl,
+}
+
+/// Color scheme the reader is drawn with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+  /// Match the terminal's background, dark when it can't be told
+  #[default]
+  Auto,
+  Dark,
+  Light,
+}
+
+/// Terminal graphics protocol used to show images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageProtocol {
+  /// Ask the terminal what it supports
+  #[default]
+  Auto,
+  Sixel,
+  Kitty,
+  Iterm,
+  /// Unicode half blocks, which work in any color terminal
+  Halfblocks,
+}
+
+/// User configuration, read from `config.toml` and overridden by CLI flags
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+  pub progress_mode: ProgressMode,
+  /// Indent the first line of each paragraph by this many spaces instead of
+  /// separating paragraphs with blank lines (0 keeps blank-line separation)
+  pub paragraph_indent: usize,
+  /// Longest line in columns, however wide the terminal; 0 for no limit
+  pub max_width: usize,
+  /// Let consecutive blocks share one blank line instead of stacking two
+  pub collapse_blank_lines: bool,
+  /// Set the first word after each heading in bold, in place of a drop cap
+  pub drop_caps: bool,
+  /// Number the lines of the chapter in a column on the left
+  pub line_numbers: bool,
+  /// Lines moved by a single `j`/`k` scroll
+  pub scroll_step: usize,
+  /// Lines of context kept above and below the word cursor, the selected
+  /// link or a search match, like vim's `scrolloff`
+  pub scroll_margin: usize,
+  /// Turning past the last chapter goes back to the first, and the other way round
+  pub wrap_around: bool,
+  /// `]` past the last heading of a chapter turns to the next chapter, and `[`
+  /// before the first one to the previous chapter, instead of stopping
+  pub heading_jumps_turn_chapters: bool,
+  pub text_direction: TextDirection,
+  /// Which way the chapter keys turn; `rtl` makes `h`/Left go forward.
+  /// `auto` follows the book's `page-progression-direction`, or
+  /// `text_direction` when that is set
+  pub page_progression: TextDirection,
+  /// Command used to look up a word, with `%s` replaced by the word; without
+  /// a `%s` the word is passed as the last argument
+  pub dictionary_command: String,
+  /// Text-to-speech command; the chapter text is written to its stdin
+  pub tts_command: String,
+  /// Treat consecutive spine items under one TOC entry as a single chapter
+  pub group_by_toc: bool,
+  /// Open new books at the first content chapter, and move past the cover and
+  /// title pages with a single chapter turn
+  pub skip_front_matter: bool,
+  /// Centered text shown for a scene break (an empty or `* * *` paragraph);
+  /// empty leaves a blank line
+  pub scene_break: String,
+  /// Text shown in place of an image; `{label}` is the alt text (or the path
+  /// when there is none), `{alt}` and `{src}` are also available. Empty hides
+  /// the placeholder
+  pub image_placeholder: String,
+  /// Show the "press 'i'" hint under image placeholders
+  pub image_hint: bool,
+  /// External command used to view images, with `%s` replaced by the image
+  /// file (e.g. `feh %s`); empty shows them in the terminal
+  pub image_viewer: String,
+  /// Command offered, with `%s` as above, for images the terminal can't draw
+  /// when `image_viewer` is empty (e.g. `xdg-open %s`); empty only explains
+  pub fallback_image_viewer: String,
+  /// Graphics protocol for showing images in the terminal, in case the
+  /// terminal's answer to `auto` detection is wrong
+  pub image_protocol: ImageProtocol,
+  pub theme: Theme,
+  /// Open the heading outline of the chapter in a sidebar on wide terminals
+  pub outline: bool,
+  /// Draw the bar above the text
+  pub show_header: bool,
+  /// Draw the bar below the text; when hidden, prompts and messages are shown
+  /// over the last line of text instead
+  pub show_footer: bool,
+  /// Header text; `{book}`, `{author}`, `{language}`, `{chapter}`, `{number}`
+  /// and `{total}` are replaced by the book title, author, language, chapter
+  /// title, chapter number and chapter count
+  pub header_format: String,
+  /// Footer title; `{percent}` and `{chapter_percent}` are book and chapter
+  /// progress, `{number}`, `{total}` and `{scroll}` as in the header and the
+  /// scroll line, `{time_left}` the estimated reading time left in the book
+  /// (e.g. `25 min left`) and `{clock}` the local time. `{time_left}` isn't in
+  /// the default, since estimating it means processing the whole book on open.
+  pub footer_format: String,
+  /// Reading speed in words per minute, used to estimate `{time_left}` when
+  /// the footer format has it
+  pub words_per_minute: usize,
+  /// Curl straight quotes and turn `--`/`---` into dashes (code is left alone)
+  pub smart_punctuation: bool,
+  /// Chapters with more HTML than this many bytes are processed in the
+  /// background, showing a loading notice meanwhile
+  pub large_chapter_size: usize,
+  /// Ask before quitting
+  pub confirm_quit: bool,
+  /// Reading keys bound to other actions, e.g. `space = "scroll-down"`
+  pub keys: HashMap<String, UserAction>,
+  /// Append each session's reading statistics to a log kept for the book
+  pub stats_log: bool,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      progress_mode: ProgressMode::default(),
+      paragraph_indent: 0,
+      max_width: 0,
+      collapse_blank_lines: false,
+      drop_caps: false,
+      line_numbers: false,
+      scroll_step: 1,
+      scroll_margin: 0,
+      wrap_around: false,
+      heading_jumps_turn_chapters: false,
+      text_direction: TextDirection::default(),
+      page_progression: TextDirection::default(),
+      dictionary_command: "dict %s".to_string(),
+      tts_command: "espeak".to_string(),
+      group_by_toc: false,
+      skip_front_matter: false,
+      scene_break: "* * *".to_string(),
+      image_placeholder: "[Image: {label}]".to_string(),
+      image_hint: true,
+      image_viewer: String::new(),
+      fallback_image_viewer: String::new(),
+      image_protocol: ImageProtocol::default(),
+      theme: Theme::default(),
+      outline: false,
+      show_header: true,
+      show_footer: true,
+      header_format: "{chapter}".to_string(),
+      footer_format: "Progress: {percent}% | Scroll: {scroll}".to_string(),
+      words_per_minute: 250,
+      smart_punctuation: false,
+      large_chapter_size: 1_000_000,
+      confirm_quit: false,
+      keys: HashMap::new(),
+      stats_log: false,
+    }
+  }
+}
+
+impl Config {
+  /// Load the config file (if any) and apply CLI overrides on top of it
+  pub fn load(args: &CliArgs) -> Result<Self, String> {
+    let path = args.config.clone().or_else(default_config_path);
+
+    let mut config = match path {
+      Some(path) if path.exists() => {
+        let raw = std::fs::read_to_string(&path)
+          .map_err(|e| format!("Failed to read config {:?}: {}", path, e))?;
+        toml::from_str(&raw).map_err(|e| format!("Invalid config {:?}: {}", path, e))?
+      }
+      _ => Config::default(),
+    };
+
+    if let Some(mode) = args.progress_mode {
+      config.progress_mode = mode;
+    }
+    if let Some(direction) = args.direction {
+      config.text_direction = direction;
+    }
+    if let Some(protocol) = args.image_protocol {
+      config.image_protocol = protocol;
+    }
+    if let Some(max_width) = args.max_width {
+      config.max_width = max_width;
+    }
+    if let Some(wpm) = args.wpm {
+      config.words_per_minute = wpm;
+    }
+
+    Ok(config)
+  }
+}
+
+/// `$XDG_CONFIG_HOME/creb/config.toml` or the platform equivalent
+fn default_config_path() -> Option<PathBuf> {
+  dirs::config_dir().map(|dir| dir.join("creb").join("config.toml"))
+}
@@ -1,3 +1,6 @@
 pub mod content;
 pub mod handler;
+pub mod layout;
 pub mod processor;
+pub mod source;
+pub mod stylesheet;
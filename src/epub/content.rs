@@ -1,14 +1,174 @@
 // src/epub/content.rs
 
-#[derive(Debug)]
+use std::collections::HashMap;
+
+/// Inline formatting carried by a run of text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextStyle {
+  pub bold: bool,
+  pub italic: bool,
+  pub underline: bool,
+  pub link: Option<usize>, // Index into the chapter's `links` when inside an `<a href>`
+}
+
+/// A piece of text sharing a single inline style
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextRun {
+  pub text: String,
+  pub style: TextStyle,
+}
+
+impl TextRun {
+  pub fn plain(text: impl Into<String>) -> Self {
+    TextRun {
+      text: text.into(),
+      style: TextStyle::default(),
+    }
+  }
+}
+
+/// Concatenate the text of styled runs
+pub fn runs_text(runs: &[TextRun]) -> String {
+  runs.iter().map(|run| run.text.as_str()).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RenderableBlock {
-  Paragraph(String),
-  Heading(usize, String),   // usize for heading level (h1, h2, etc.)
-  Image(String),            // Path or URL to the image
+  Paragraph(Vec<TextRun>),
+  Centered(Vec<TextRun>), // Paragraph set with `<center>` or `text-align: center`
+  Preformatted(Vec<TextRun>), // Text whose line breaks and spacing are kept, like verse
+  Code(Vec<TextRun>),     // Preformatted program code, whose lines are best not broken
+  Heading(usize, Vec<TextRun>), // usize for heading level (h1, h2, etc.)
+  Image {
+    src: String,                   // Path or URL to the image
+    alt: Option<String>,           // The element's `alt` text, when it has any
+    caption: Option<Vec<TextRun>>, // The `<figcaption>` of the figure holding it
+  },
   ImagePlaceholder(String), // For images that couldn't be loaded
+  SceneBreak,               // An empty or ornament-only paragraph between scenes
+}
+
+impl RenderableBlock {
+  /// Shift link indices, for when chapters' link lists are concatenated
+  pub fn offset_links(&mut self, offset: usize) {
+    if let RenderableBlock::Paragraph(runs)
+    | RenderableBlock::Heading(_, runs)
+    | RenderableBlock::Centered(runs)
+    | RenderableBlock::Preformatted(runs)
+    | RenderableBlock::Code(runs)
+    | RenderableBlock::Image {
+      caption: Some(runs),
+      ..
+    } = self
+    {
+      for run in runs {
+        if let Some(link) = &mut run.style.link {
+          *link += offset;
+        }
+      }
+    }
+  }
+
+  /// Text describing an image block: its alt text, or the image's file name
+  /// when it has none
+  pub fn image_label(&self) -> Option<&str> {
+    match self {
+      RenderableBlock::Image { src, alt, .. } => Some(alt.as_deref().unwrap_or_else(|| {
+        std::path::Path::new(src)
+          .file_name()
+          .and_then(|name| name.to_str())
+          .unwrap_or(src)
+      })),
+      _ => None,
+    }
+  }
+}
+
+/// A footnote or endnote taken out of the main text
+#[derive(Debug, Clone)]
+pub struct Footnote {
+  pub text: String,
 }
 
 #[derive(Debug)]
 pub struct RenderableChapter {
   pub blocks: Vec<RenderableBlock>,
+  pub rtl: bool, // Set when the document declares `dir="rtl"`
+  pub footnotes: Vec<Footnote>,
+  pub title: Option<String>, // First heading of an `epub:type="chapter"` section
+  pub links: Vec<String>,    // Target of every link, indexed by `TextStyle::link`
+  /// Block each element `id` appears in, for links to `#fragment`s
+  pub anchors: HashMap<String, usize>,
+}
+
+impl RenderableChapter {
+  /// Number of characters of readable text in the chapter
+  pub fn text_len(&self) -> usize {
+    self.text_len_before(self.blocks.len())
+  }
+
+  /// Number of characters of readable text in the blocks before `block`
+  pub fn text_len_before(&self, block: usize) -> usize {
+    self
+      .blocks
+      .iter()
+      .take(block)
+      .map(|block| match block {
+        RenderableBlock::Paragraph(runs)
+        | RenderableBlock::Heading(_, runs)
+        | RenderableBlock::Centered(runs)
+        | RenderableBlock::Preformatted(runs)
+        | RenderableBlock::Code(runs) => runs.iter().map(|run| run.text.chars().count()).sum(),
+        RenderableBlock::Image {
+          caption: Some(runs),
+          ..
+        } => runs.iter().map(|run| run.text.chars().count()).sum(),
+        _ => 0,
+      })
+      .sum()
+  }
+
+  /// Number of words of readable text in the chapter
+  pub fn word_count(&self) -> usize {
+    self
+      .blocks
+      .iter()
+      .map(|block| match block {
+        RenderableBlock::Paragraph(runs)
+        | RenderableBlock::Heading(_, runs)
+        | RenderableBlock::Centered(runs)
+        | RenderableBlock::Preformatted(runs)
+        | RenderableBlock::Code(runs) => runs_text(runs).split_whitespace().count(),
+        RenderableBlock::Image {
+          caption: Some(runs),
+          ..
+        } => runs_text(runs).split_whitespace().count(),
+        _ => 0,
+      })
+      .sum()
+  }
+
+  /// Readable text of the blocks from `start_block` on, one block per paragraph
+  pub fn plain_text_from(&self, start_block: usize) -> String {
+    self
+      .blocks
+      .iter()
+      .skip(start_block)
+      .filter_map(|block| match block {
+        RenderableBlock::Paragraph(runs)
+        | RenderableBlock::Heading(_, runs)
+        | RenderableBlock::Centered(runs)
+        | RenderableBlock::Preformatted(runs)
+        | RenderableBlock::Code(runs) => Some(runs_text(runs)),
+        // Only described images are worth reading out
+        RenderableBlock::Image { alt, caption, .. } if alt.is_some() || caption.is_some() => {
+          let caption = caption.as_deref().map(runs_text);
+          let description: Vec<String> = alt.iter().cloned().chain(caption).collect();
+          Some(format!("Image: {}", description.join(". ")))
+        }
+        _ => None,
+      })
+      .collect::<Vec<_>>()
+      .join("\n\n")
+  }
 }
@@ -1,34 +1,389 @@
+use super::content::RenderableChapter;
+use super::processor::{ProcessOptions, nav_toc_links, process_chapter_html};
+use super::stylesheet::Stylesheet;
+use crate::timing;
 use epub::doc::EpubDoc;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
 use std::fs::File;
-use std::io::BufReader;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+/// An attribute in a tag's markup, with its whole name (so `data-id` is never
+/// taken for `id`) and its value in either kind of quotes
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r#"\s([\w:.-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap()
+});
+/// The OPF's `<spine>` start tag
+static SPINE_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<spine\b[^>]*>").unwrap());
+/// The `href` of an SVG `<image>`, with or without the `xlink:` prefix
+static SVG_IMAGE_HREF: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r#"<image\b[^>]*?[\s:]href\s*=\s*["']([^"']+)["']"#).unwrap());
+/// An `<item>` of the OPF manifest
+static MANIFEST_ITEM: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<item\b[^>]*>").unwrap());
+/// A `<reference>` of the OPF guide
+static GUIDE_REFERENCE: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"<reference\b[^>]*>").unwrap());
+/// An `epub:type` marking a document as a cover or title page
+static FRONT_MATTER_TYPE: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r#"epub:type\s*=\s*["'][^"']*\b(cover|titlepage)\b"#).unwrap()
+});
+
+/// A table of contents entry that points into the spine
+#[derive(Debug, Clone)]
+pub struct TocEntry {
+  pub title: String,
+  pub chapter: usize,           // Spine index
+  pub fragment: Option<String>, // Id of the section the entry points at, if any
+  pub depth: usize,             // Nesting level, 0 for top-level entries
+}
 
 pub struct EpubHandler {
   pub doc: EpubDoc<BufReader<File>>,
   pub base_path: PathBuf,
   current_chapter_path: Option<PathBuf>,
+  chapter_lengths: Vec<usize>,
+  chapter_word_counts: Vec<usize>,
+  page_progression_rtl: bool,
+  stylesheet: Stylesheet,
+  toc_chapter_starts: Vec<bool>,
+  toc: Vec<TocEntry>, // Flattened TOC, in reading order
+  first_content_chapter: usize,
+  navigation_chapter: Option<usize>, // Spine index of the EPUB 3 nav document
 }
 
 impl EpubHandler {
-  pub fn new(path: PathBuf) -> Result<Self, String> {
-    let doc = EpubDoc::new(path.clone())
+  /// Open an EPUB file and read its spine, TOC and stylesheets
+  pub fn open(path: PathBuf) -> Result<Self, String> {
+    if !path.is_file() {
+      return Err(format!("No such file: {:?}", path));
+    }
+    // Books are recognised by their content rather than their name, so
+    // `.kepub.epub` files and files without an extension open too
+    if !is_zip_archive(&path) {
+      return Err(format!(
+        "Not an EPUB (not a ZIP archive) - path: {:?}",
+        path
+      ));
+    }
+    let started = Instant::now();
+
+    let mut doc = EpubDoc::new(path.clone())
       .map_err(|e| format!("Failed to open EPUB: {} - path: {:?}", e, path))?;
+
+    if doc.get_num_pages() == 0 {
+      return Err(format!(
+        "This EPUB contains no readable chapters - path: {:?}",
+        path
+      ));
+    }
+
+    // The epub crate doesn't expose the spine attributes, so read them from the OPF
+    let root_file = doc.root_file.clone();
+    let opf = doc.get_resource_str_by_path(&root_file);
+    let page_progression_rtl = opf
+      .as_deref()
+      .and_then(|opf| SPINE_TAG.find(opf))
+      .and_then(|tag| attribute(tag.as_str(), "page-progression-direction"))
+      .is_some_and(|direction| direction.trim().eq_ignore_ascii_case("rtl"));
+    let navigation_item = opf.as_deref().and_then(find_navigation_item);
+    let navigation_chapter = navigation_item
+      .as_ref()
+      .and_then(|id| doc.spine.iter().position(|item| &item.idref == id));
+    let first_content_chapter =
+      find_first_content_chapter(&mut doc, opf.as_deref(), navigation_chapter);
+
+    // Collect class-based formatting from every stylesheet in the book
+    let mut stylesheet = Stylesheet::default();
+    let css_paths: Vec<PathBuf> = doc
+      .resources
+      .values()
+      .filter(|(_, mime)| mime == "text/css")
+      .map(|(path, _)| path.clone())
+      .collect();
+    for css_path in css_paths {
+      if let Some(css) = doc.get_resource_str_by_path(&css_path) {
+        stylesheet.add_css(&css);
+      }
+    }
+
+    // Flatten the TOC into (depth, label, target) in reading order. The epub
+    // crate only parses the NCX, so books with just an EPUB 3 nav document
+    // have that read here instead
+    let mut outline = Vec::new();
+    let mut pending: Vec<_> = doc.toc.iter().rev().map(|nav| (0, nav.clone())).collect();
+    while let Some((depth, nav_point)) = pending.pop() {
+      outline.push((
+        depth,
+        nav_point.label,
+        nav_point.content.to_string_lossy().into_owned(),
+      ));
+      pending.extend(
+        nav_point
+          .children
+          .into_iter()
+          .rev()
+          .map(|child| (depth + 1, child)),
+      );
+    }
+    if outline.is_empty()
+      && let Some((nav_path, _mime)) = navigation_item.and_then(|id| doc.resources.get(&id).cloned())
+      && let Some(nav) = doc.get_resource_str_by_path(&nav_path)
+    {
+      // Links in the nav document are relative to it
+      let nav_dir = nav_path.parent().unwrap_or_else(|| Path::new(""));
+      outline = nav_toc_links(&nav)
+        .into_iter()
+        .map(|(depth, label, href)| {
+          let target = normalize_path(&nav_dir.join(href));
+          (depth, label, target.to_string_lossy().into_owned())
+        })
+        .collect();
+    }
+
+    // Keep the entries that point into the spine
+    let mut toc = Vec::new();
+    for (depth, label, target) in outline {
+      let (path, fragment) = match target.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (target.as_str(), None),
+      };
+      // Several entries may point at sections of one file; they share its spine index
+      if let Some(chapter) = doc.resource_uri_to_chapter(&PathBuf::from(path)) {
+        toc.push(TocEntry {
+          title: label.trim().to_string(),
+          chapter,
+          fragment: fragment
+            .filter(|fragment| !fragment.is_empty())
+            .map(|fragment| {
+              percent_decode_str(fragment)
+                .decode_utf8_lossy()
+                .into_owned()
+            }),
+          depth,
+        });
+      }
+    }
+
+    // Spine items referenced from the TOC start a new logical chapter
+    let mut toc_chapter_starts = vec![false; doc.get_num_pages()];
+    for entry in &toc {
+      if let Some(start) = toc_chapter_starts.get_mut(entry.chapter) {
+        *start = true;
+      }
+    }
+    // Without a usable TOC every spine item stands on its own
+    if !toc_chapter_starts.contains(&true) {
+      toc_chapter_starts.fill(true);
+    }
+    if let Some(first) = toc_chapter_starts.first_mut() {
+      *first = true;
+    }
+    // The nav document never shares a chapter with the text around it
+    if let Some(nav) = navigation_chapter {
+      for index in [nav, nav + 1] {
+        if let Some(start) = toc_chapter_starts.get_mut(index) {
+          *start = true;
+        }
+      }
+    }
+
+    timing::record(started, || format!("open {:?}", path));
+
     Ok(EpubHandler {
       doc,
       base_path: path,
       current_chapter_path: None,
+      chapter_lengths: Vec::new(),
+      chapter_word_counts: Vec::new(),
+      page_progression_rtl,
+      stylesheet,
+      toc_chapter_starts,
+      toc,
+      first_content_chapter,
+      navigation_chapter,
     })
   }
 
-  pub fn get_chapter_count(&self) -> usize {
-    self.doc.get_num_pages()
+  /// Spine items belonging to the same TOC chapter as `chapter_index`
+  ///
+  /// Spine items the TOC doesn't point at are treated as continuations of the
+  /// chapter before them.
+  pub fn chapter_group(&self, chapter_index: usize) -> std::ops::Range<usize> {
+    let starts = &self.toc_chapter_starts;
+    if chapter_index >= starts.len() {
+      return chapter_index..chapter_index + 1;
+    }
+
+    let start = (0..=chapter_index)
+      .rev()
+      .find(|&index| starts[index])
+      .unwrap_or(0);
+    let end = (chapter_index + 1..starts.len())
+      .find(|&index| starts[index])
+      .unwrap_or(starts.len());
+
+    start..end
   }
 
-  pub fn get_chapter_content_raw(&mut self, chapter_index: usize) -> Result<String, String> {
-    if chapter_index >= self.get_chapter_count() {
-      return Err(format!("Chapter index {} out of bounds", chapter_index));
+  /// Spine index where the body of the book starts, after any cover, title
+  /// page or other front matter
+  pub fn first_content_chapter(&self) -> usize {
+    self.first_content_chapter
+  }
+
+  /// Whether a spine item is the book's nav document, a list of links that
+  /// linear reading passes over
+  pub fn is_navigation(&self, chapter_index: usize) -> bool {
+    self.navigation_chapter == Some(chapter_index)
+  }
+
+  /// Class-based formatting parsed from the book's CSS
+  pub fn stylesheet(&self) -> &Stylesheet {
+    &self.stylesheet
+  }
+
+  /// Whether the OPF spine declares `page-progression-direction="rtl"`
+  pub fn is_page_progression_rtl(&self) -> bool {
+    self.page_progression_rtl
+  }
+
+  /// Whether keys pointing right (`l`, →) go to the next chapter; in books
+  /// whose pages turn right to left they go back instead. `rtl` overrides the
+  /// book's own page progression when given.
+  pub fn right_turns_forward(&self, rtl: Option<bool>) -> bool {
+    !rtl.unwrap_or(self.page_progression_rtl)
+  }
+
+  /// Title from `dc:title`
+  pub fn book_title(&self) -> Option<String> {
+    self.doc.mdata("title")
+  }
+
+  /// Author from `dc:creator`
+  pub fn book_author(&self) -> Option<String> {
+    self.doc.mdata("creator")
+  }
+
+  /// Primary language tag from `dc:language`, e.g. `en` or `zh-Hant`
+  pub fn book_language(&self) -> Option<String> {
+    self.doc.mdata("language")
+  }
+
+  /// Whether the book's primary language is written right-to-left
+  pub fn is_rtl_language(&self) -> bool {
+    const RTL_LANGUAGES: [&str; 6] = ["ar", "he", "fa", "ur", "yi", "ps"];
+
+    self.book_language().is_some_and(|language| {
+      let primary = language.split(['-', '_']).next().unwrap_or("");
+      RTL_LANGUAGES
+        .iter()
+        .any(|rtl| primary.eq_ignore_ascii_case(rtl))
+    })
+  }
+
+  /// Measure the text length of every chapter once, for length-weighted progress
+  ///
+  /// Chapters that fail to load count as empty.
+  pub fn compute_chapter_lengths(&mut self) {
+    if !self.chapter_lengths.is_empty() {
+      return;
+    }
+
+    (self.chapter_lengths, self.chapter_word_counts) = (0..self.get_chapter_count())
+      .map(|index| {
+        self
+          .chapter(index)
+          .map(|chapter| (chapter.text_len(), chapter.word_count()))
+          .unwrap_or((0, 0))
+      })
+      .unzip();
+  }
+
+  /// Process a spine item into blocks with the book's stylesheet and the
+  /// default options
+  pub fn chapter(&mut self, chapter_index: usize) -> Result<RenderableChapter, String> {
+    let raw_html = self.get_chapter_content_raw(chapter_index)?;
+    Ok(process_chapter_html(
+      &raw_html,
+      &self.stylesheet,
+      &ProcessOptions::default(),
+    ))
+  }
+
+  /// Per-chapter text lengths, empty until `compute_chapter_lengths` has run
+  pub fn get_chapter_lengths(&self) -> &[usize] {
+    &self.chapter_lengths
+  }
+
+  /// Per-chapter word counts, empty until `compute_chapter_lengths` has run
+  pub fn get_chapter_word_counts(&self) -> &[usize] {
+    &self.chapter_word_counts
+  }
+
+  /// Path of a spine item inside the EPUB
+  pub fn spine_path(&self, index: usize) -> Option<String> {
+    let item = self.doc.spine.get(index)?;
+    let (path, _mime) = self.doc.resources.get(&item.idref)?;
+    Some(path.to_string_lossy().into_owned())
+  }
+
+  /// Spine index of the item at a path inside the EPUB
+  pub fn spine_index(&self, path: &str) -> Option<usize> {
+    self.doc.resource_uri_to_chapter(&PathBuf::from(path))
+  }
+
+  /// Spine index of a chapter named by its href, which may be percent-encoded
+  /// or leave out the leading directories (`ch05.xhtml` for `OEBPS/text/ch05.xhtml`)
+  pub fn find_spine_item(&self, href: &str) -> Option<usize> {
+    let decoded = percent_decode_str(href).decode_utf8_lossy();
+    let target = normalize_path(Path::new(decoded.as_ref()));
+    if target.as_os_str().is_empty() {
+      return None;
     }
 
+    let paths: Vec<PathBuf> = (0..self.get_chapter_count())
+      .map(|index| normalize_path(Path::new(&self.spine_path(index).unwrap_or_default())))
+      .collect();
+    paths
+      .iter()
+      .position(|path| *path == target)
+      .or_else(|| paths.iter().position(|path| path.ends_with(&target)))
+  }
+
+  pub fn toc_entries(&self) -> &[TocEntry] {
+    &self.toc
+  }
+
+  /// A TOC entry's section as a resolved link, the way chapter anchors are keyed
+  pub fn toc_anchor(&self, entry: &TocEntry) -> Option<String> {
+    let fragment = entry.fragment.as_ref()?;
+    let path = self.spine_path(entry.chapter)?;
+    Some(format!(
+      "{}#{}",
+      normalize_path(Path::new(&path)).to_string_lossy(),
+      fragment
+    ))
+  }
+
+  /// Title of the first TOC entry pointing at a spine item
+  pub fn chapter_title(&self, chapter_index: usize) -> Option<&str> {
+    self
+      .toc
+      .iter()
+      .find(|entry| entry.chapter == chapter_index && !entry.title.is_empty())
+      .map(|entry| entry.title.as_str())
+  }
+
+  pub fn get_chapter_count(&self) -> usize {
+    self.doc.get_num_pages()
+  }
+
+  /// Make a spine item current, so relative resource paths resolve against it
+  pub fn select_chapter(&mut self, chapter_index: usize) -> Result<(), String> {
     if !self.doc.set_current_page(chapter_index) {
       return Err(format!(
         "Failed to set current chapter to {}",
@@ -37,22 +392,68 @@ impl EpubHandler {
     }
 
     // Store the current chapter path for resolving relative image paths
-    if let Some(current) = self.doc.get_current() {
-      self.current_chapter_path = Some(PathBuf::from(&current.1));
+    self.current_chapter_path = self.doc.get_current_path();
+
+    Ok(())
+  }
+
+  pub fn get_chapter_content_raw(&mut self, chapter_index: usize) -> Result<String, String> {
+    if chapter_index >= self.get_chapter_count() {
+      return Err(format!("Chapter index {} out of bounds", chapter_index));
     }
 
+    self.select_chapter(chapter_index)?;
+
     // Get the current chapter content
     match self.doc.get_current() {
-      Some(current) => {
-        // The current object contains the raw bytes in .0
-        // Let's try to convert it to a string
-        String::from_utf8(current.0)
-          .map_err(|_| "Failed to decode chapter content as UTF-8".to_string())
-      }
+      // The current object contains the raw bytes in .0
+      Some(current) => decode_chapter(current.0),
       None => Err("Failed to get chapter content".to_string()),
     }
   }
 
+  /// Resolve a link in the current chapter to a full resource path (keeping
+  /// any `#fragment`); links to other sites are returned unchanged
+  pub fn resolve_link(&self, href: &str) -> String {
+    if href.contains("://") || href.starts_with("mailto:") {
+      return href.to_string();
+    }
+    let Some(chapter_path) = &self.current_chapter_path else {
+      return href.to_string();
+    };
+
+    let (path, fragment) = match href.split_once('#') {
+      Some((path, fragment)) => (path, Some(fragment)),
+      None => (href, None),
+    };
+    // A bare `#fragment` points into the chapter itself
+    let mut resolved = if path.is_empty() {
+      chapter_path.clone()
+    } else {
+      chapter_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .to_path_buf()
+    };
+    resolved.push(path);
+    // Links are percent-decoded, but anchors are keyed by the manifest path as written
+    let resolved = normalize_path(&resolved);
+    let resolved = self
+      .manifest_path(&resolved.to_string_lossy())
+      .map_or(resolved, |path| normalize_path(&path));
+
+    match fragment {
+      Some(fragment) => format!("{}#{}", resolved.to_string_lossy(), fragment),
+      None => resolved.to_string_lossy().into_owned(),
+    }
+  }
+
+  /// Spine index a resolved internal link points at
+  pub fn link_target(&self, resolved_href: &str) -> Option<usize> {
+    let path = resolved_href.split('#').next().unwrap_or_default();
+    self.doc.resource_uri_to_chapter(&PathBuf::from(path))
+  }
+
   /// Resolve a relative path based on the current chapter's location
   ///
   /// # Arguments
@@ -73,7 +474,7 @@ impl EpubHandler {
       let resolved_path = chapter_dir.join(rel_path);
 
       // Normalize the path to remove .. components
-      let normalized_path = resolved_path.components().collect::<PathBuf>();
+      let normalized_path = normalize_path(&resolved_path);
 
       // Convert back to string
       normalized_path
@@ -101,80 +502,261 @@ impl EpubHandler {
       .resolve_relative_path(resource_path)
       .unwrap_or_else(|_| resource_path.to_string());
 
-    // Collect resource keys to avoid borrowing issues
-    let resource_keys: Vec<String> = self.doc.resources.keys().cloned().collect();
-
-    // Look up the resource in the EPUB's resources map
-    if let Some((path, _mime_type)) = self.doc.resources.get(&resolved_path) {
-      // Clone the path to avoid borrowing issues
-      let path_clone = path.clone();
-
-      // Extract the resource data
-      let data = self.doc.get_resource_by_path(&path_clone).ok_or_else(|| {
+    let mut full_path = self
+      .find_resource(resource_path, &resolved_path)
+      .ok_or_else(|| {
         format!(
-          "Failed to extract resource {}: data not found",
-          resource_path
+          "Resource not found: {} (resolved from {})",
+          resolved_path, resource_path
         )
       })?;
 
-      // Create a temporary file to store the resource
-      let temp_dir = std::env::temp_dir();
-      let path_buf = PathBuf::from(&resolved_path);
-      let file_name = path_buf
-        .file_name()
-        .ok_or_else(|| "Invalid resource path".to_string())?
-        .to_str()
-        .ok_or_else(|| "Invalid resource path encoding".to_string())?;
+    // Extract the resource data
+    let mut data = self.doc.get_resource_by_path(&full_path).ok_or_else(|| {
+      format!(
+        "Failed to extract resource {}: data not found",
+        resource_path
+      )
+    })?;
+
+    // Covers are often an SVG wrapping a single raster image, which is what
+    // can actually be shown
+    if is_svg(&full_path)
+      && let Some(raster_path) = self.embedded_image(&full_path, &data)
+      && let Some(raster) = self.doc.get_resource_by_path(&raster_path)
+    {
+      data = raster;
+      full_path = raster_path;
+    }
 
-      let temp_path = temp_dir.join(file_name);
+    write_temp_resource(&self.base_path, &full_path.to_string_lossy(), &data)
+  }
 
-      // Write the data to the temporary file
-      std::fs::write(&temp_path, data)
-        .map_err(|e| format!("Failed to write resource to temp file: {}", e))?;
+  /// Container path of the image an SVG document embeds with `<image href>`
+  fn embedded_image(&self, svg_path: &Path, svg: &[u8]) -> Option<PathBuf> {
+    let svg = String::from_utf8_lossy(svg);
+    let href = SVG_IMAGE_HREF.captures(&svg)?[1].to_string();
+    if href.starts_with("data:") {
+      return None;
+    }
 
-      Ok(temp_path)
-    } else {
-      // Try to find the resource with a different approach
-      // The resource path might be relative to the current chapter's path
-      // Let's try to find any resource that ends with this path
-      for key in resource_keys {
-        if let Some((full_path, _mime_type)) = self.doc.resources.get(&key) {
-          if full_path.ends_with(&resolved_path) || full_path.ends_with(resource_path) {
-            // Clone the path to avoid borrowing issues
-            let path_clone = full_path.clone();
-
-            // Extract the resource data
-            let data = self.doc.get_resource_by_path(&path_clone).ok_or_else(|| {
-              format!(
-                "Failed to extract resource {}: data not found",
-                resource_path
-              )
-            })?;
-
-            // Create a temporary file to store the resource
-            let temp_dir = std::env::temp_dir();
-            let path_buf = PathBuf::from(resource_path);
-            let file_name = path_buf
-              .file_name()
-              .ok_or_else(|| "Invalid resource path".to_string())?
-              .to_str()
-              .ok_or_else(|| "Invalid resource path encoding".to_string())?;
-
-            let temp_path = temp_dir.join(file_name);
-
-            // Write the data to the temporary file
-            std::fs::write(&temp_path, data)
-              .map_err(|e| format!("Failed to write resource to temp file: {}", e))?;
-
-            return Ok(temp_path);
-          }
-        }
+    // The reference is relative to the SVG document, not the chapter
+    let svg_dir = svg_path.parent().unwrap_or_else(|| Path::new(""));
+    let resolved = normalize_path(&svg_dir.join(&href));
+    self.find_resource(&href, &resolved.to_string_lossy())
+  }
+
+  /// Find a resource's path in the container
+  ///
+  /// The resolved and raw paths are tried first as manifest ids and exact
+  /// container paths, then as path suffixes, since books disagree on how their
+  /// hrefs are written. Either may be percent-decoded or not.
+  fn find_resource(&self, resource_path: &str, resolved_path: &str) -> Option<PathBuf> {
+    let candidates = [resolved_path, resource_path];
+
+    for candidate in candidates {
+      if let Some((path, _mime_type)) = self.doc.resources.get(candidate) {
+        return Some(path.clone());
+      }
+      if let Some(path) = self.manifest_path(candidate) {
+        return Some(path);
       }
+    }
+
+    // The path might be relative to somewhere other than the current chapter
+    candidates.iter().find_map(|candidate| {
+      let normalized = normalize_path(Path::new(candidate));
+      if normalized.as_os_str().is_empty() {
+        return None;
+      }
+      self
+        .doc
+        .resources
+        .values()
+        .find(|(path, _)| path.ends_with(&normalized) || decode_path(path).ends_with(&normalized))
+        .map(|(path, _)| path.clone())
+    })
+  }
+
+  /// Container path of the manifest item at `path`
+  ///
+  /// The epub crate keeps manifest paths as the OPF writes them, so they are
+  /// compared both as written and percent-decoded.
+  fn manifest_path(&self, path: &str) -> Option<PathBuf> {
+    let target = normalize_path(Path::new(path));
+    self
+      .doc
+      .resources
+      .values()
+      .map(|(path, _)| path)
+      .find(|path| normalize_path(path) == target || normalize_path(&decode_path(path)) == target)
+      .cloned()
+  }
+}
+
+/// Decode a chapter's bytes as UTF-8 or, going by its byte order mark or its
+/// first `<`, UTF-16; a byte order mark is dropped rather than shown as text
+fn decode_chapter(bytes: Vec<u8>) -> Result<String, String> {
+  let utf16 = |bytes: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+    let units: Vec<u16> = bytes
+      .chunks_exact(2)
+      .map(|pair| from_bytes([pair[0], pair[1]]))
+      .collect();
+    String::from_utf16(&units).map_err(|_| "Failed to decode chapter content as UTF-16".to_string())
+  };
+
+  match bytes.as_slice() {
+    [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8(rest.to_vec())
+      .map_err(|_| "Failed to decode chapter content as UTF-8".to_string()),
+    [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+    [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+    [b'<', 0, ..] => utf16(&bytes, u16::from_le_bytes),
+    [0, b'<', ..] => utf16(&bytes, u16::from_be_bytes),
+    _ => {
+      String::from_utf8(bytes).map_err(|_| "Failed to decode chapter content as UTF-8".to_string())
+    }
+  }
+}
+
+/// Percent-decode a container path (`image%20name.jpg`)
+fn decode_path(path: &Path) -> PathBuf {
+  PathBuf::from(
+    percent_decode_str(&path.to_string_lossy())
+      .decode_utf8_lossy()
+      .as_ref(),
+  )
+}
+
+/// Whether a container path names an SVG document
+fn is_svg(path: &Path) -> bool {
+  path
+    .extension()
+    .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"))
+}
+
+/// Whether a file starts like a ZIP archive, which every EPUB is
+fn is_zip_archive(path: &Path) -> bool {
+  let mut magic = [0; 4];
+  File::open(path)
+    .and_then(|mut file| file.read_exact(&mut magic))
+    .is_ok()
+    && magic == *b"PK\x03\x04"
+}
 
-      Err(format!(
-        "Resource not found: {} (resolved from {})",
-        resolved_path, resource_path
-      ))
+/// Resolve `.` and `..` components of a path inside the container
+fn normalize_path(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      std::path::Component::ParentDir => {
+        normalized.pop();
+      }
+      std::path::Component::Normal(part) => normalized.push(part),
+      _ => {}
+    }
+  }
+  normalized
+}
+
+/// Write an extracted resource to a temporary file named after it
+fn write_temp_resource(
+  book_path: &Path,
+  resource_path: &str,
+  data: &[u8],
+) -> Result<PathBuf, String> {
+  let temp_path = std::env::temp_dir().join(temp_file_name(book_path, resource_path));
+  std::fs::write(&temp_path, data)
+    .map_err(|e| format!("Failed to write resource to temp file: {}", e))?;
+  Ok(temp_path)
+}
+
+/// A name unique to the book and the resource's whole path, so `a/fig.png`
+/// and `b/fig.png` don't overwrite each other; the file name is kept at the
+/// end, extension included, for viewers that go by it
+pub fn temp_file_name(book_path: &Path, resource_path: &str) -> String {
+  let mut hasher = DefaultHasher::new();
+  book_path.hash(&mut hasher);
+  resource_path.hash(&mut hasher);
+  let hash = hasher.finish();
+
+  match Path::new(resource_path)
+    .file_name()
+    .and_then(|name| name.to_str())
+  {
+    Some(name) => format!("creb-{:016x}-{}", hash, name),
+    None => format!("creb-resource-{:016x}", hash),
+  }
+}
+
+// Value of an attribute in a tag's markup
+fn attribute(tag: &str, name: &str) -> Option<String> {
+  ATTRIBUTE
+    .captures_iter(tag)
+    .find(|captures| &captures[1] == name)
+    .and_then(|captures| captures.get(2).or_else(|| captures.get(3)))
+    .map(|value| value.as_str().to_string())
+}
+
+/// Find the manifest id of the nav document, by the `nav` property EPUB 3
+/// requires on its item
+fn find_navigation_item(opf: &str) -> Option<String> {
+  MANIFEST_ITEM.find_iter(opf).find_map(|tag| {
+    let tag = tag.as_str();
+    attribute(tag, "properties")?
+      .split_whitespace()
+      .any(|property| property == "nav")
+      .then(|| attribute(tag, "id"))?
+  })
+}
+
+/// Find where the body of the book starts: the OPF guide's `text` reference
+/// when there is one, otherwise the first spine item that isn't the nav
+/// document or marked (by `epub:type` or its id) as a cover or title page
+fn find_first_content_chapter(
+  doc: &mut EpubDoc<BufReader<File>>,
+  opf: Option<&str>,
+  navigation_chapter: Option<usize>,
+) -> usize {
+  let opf_dir = doc
+    .root_file
+    .parent()
+    .map(Path::to_path_buf)
+    .unwrap_or_default();
+  let guide_text = opf
+    .into_iter()
+    .flat_map(|opf| GUIDE_REFERENCE.find_iter(opf))
+    .find_map(|tag| {
+      let tag = tag.as_str();
+      if attribute(tag, "type")?.eq_ignore_ascii_case("text") {
+        let href = attribute(tag, "href")?;
+        let path = opf_dir.join(href.split('#').next().unwrap_or_default());
+        doc.resource_uri_to_chapter(&path)
+      } else {
+        None
+      }
+    });
+  if let Some(chapter) = guide_text {
+    return chapter;
+  }
+
+  let spine: Vec<String> = doc.spine.iter().map(|item| item.idref.clone()).collect();
+  for (index, idref) in spine.iter().enumerate() {
+    let id = idref.to_lowercase();
+    if navigation_chapter == Some(index) {
+      continue;
+    }
+    if id.contains("cover") || id.contains("titlepage") || id.contains("title-page") {
+      continue;
+    }
+    let Some((path, _mime)) = doc.resources.get(idref).cloned() else {
+      return index;
+    };
+    let is_front_matter = doc
+      .get_resource_str_by_path(&path)
+      .is_some_and(|html| FRONT_MATTER_TYPE.is_match(&html));
+    if !is_front_matter {
+      return index;
     }
   }
+  0
 }
@@ -0,0 +1,670 @@
+use super::content::{RenderableBlock, RenderableChapter, TextRun, TextStyle};
+use std::collections::{HashMap, VecDeque};
+use unicode_width::UnicodeWidthChar;
+
+/// Settings that decide how blocks are laid out as lines
+#[derive(Debug, Clone)]
+pub struct LayoutOptions {
+  /// Spaces before the first line of a paragraph; indented paragraphs follow
+  /// each other without blank lines, like in print
+  pub paragraph_indent: usize,
+  /// Merge a block's leading blank line into the previous block's trailing one
+  pub collapse_blank_lines: bool,
+  /// Embolden the opening word of the first paragraph after a heading
+  pub drop_caps: bool,
+  /// Centered text marking a scene break; empty leaves a blank line
+  pub scene_break: String,
+  /// Text standing in for images, with `{label}`, `{alt}` and `{src}` fields
+  pub image_placeholder: String,
+  /// Follow image placeholders with a hint on how to view the image
+  pub image_hint: bool,
+  /// Dimensions and file size of images by source, shown after their placeholder
+  pub image_sizes: HashMap<String, String>,
+  /// Lay out image blocks at all; hidden ones take no lines
+  pub show_images: bool,
+  /// Break code lines that don't fit, rather than leaving them to be scrolled
+  /// sideways
+  pub wrap_code: bool,
+  /// Width of the whole screen, which sizes the rules around headings
+  pub screen_width: usize,
+}
+
+impl Default for LayoutOptions {
+  fn default() -> Self {
+    LayoutOptions {
+      paragraph_indent: 0,
+      collapse_blank_lines: false,
+      drop_caps: false,
+      scene_break: "* * *".to_string(),
+      image_placeholder: "[Image: {label}]".to_string(),
+      image_hint: false,
+      image_sizes: HashMap::new(),
+      show_images: true,
+      wrap_code: true,
+      screen_width: 80,
+    }
+  }
+}
+
+/// A wrapped line of a chapter
+#[derive(Debug, Clone, Default)]
+pub struct RenderedLine {
+  pub runs: Vec<TextRun>, // Neighbouring characters sharing a style form one run
+  pub indent: usize,      // Spaces before the text
+  pub centered: bool,
+  pub image: bool,  // Set on the first line standing in for an image
+  pub code: bool,   // Part of a code block
+  pub block: usize, // Index of the block the line belongs to
+}
+
+impl RenderedLine {
+  /// Whether the line is an empty line between blocks
+  pub fn is_blank(&self) -> bool {
+    self.indent == 0 && self.runs.iter().all(|run| run.text.is_empty())
+  }
+}
+
+/// Lines of a chapter, laid out one block at a time as they are taken
+pub struct Lines<'a> {
+  blocks: std::iter::Enumerate<std::slice::Iter<'a, RenderableBlock>>,
+  width: usize,
+  options: &'a LayoutOptions,
+  pending: VecDeque<RenderedLine>, // Laid out but not yet taken
+  after_blank: bool,               // Whether the last line taken was blank
+  after_heading: bool,             // Whether the last block laid out was a heading
+}
+
+impl RenderableChapter {
+  /// Wrap the chapter into lines of at most `width` columns
+  pub fn lines<'a>(&'a self, width: usize, options: &'a LayoutOptions) -> Lines<'a> {
+    Lines {
+      blocks: self.blocks.iter().enumerate(),
+      width,
+      options,
+      pending: VecDeque::new(),
+      after_blank: false,
+      after_heading: false,
+    }
+  }
+}
+
+impl Iterator for Lines<'_> {
+  type Item = RenderedLine;
+
+  fn next(&mut self) -> Option<RenderedLine> {
+    loop {
+      if let Some(line) = self.pending.pop_front() {
+        self.after_blank = line.is_blank();
+        return Some(line);
+      }
+      let (index, block) = self.blocks.next()?;
+      self.lay_out_block(index, block);
+      self.after_heading = matches!(block, RenderableBlock::Heading(..));
+    }
+  }
+}
+
+impl Lines<'_> {
+  // Wrap a block into pending lines, with blank lines around it for spacing
+  fn lay_out_block(&mut self, index: usize, block: &RenderableBlock) {
+    let width = self.width;
+    match block {
+      RenderableBlock::Image { .. } | RenderableBlock::ImagePlaceholder(_)
+        if !self.options.show_images => {}
+      RenderableBlock::Paragraph(runs) => {
+        let indent = self.options.paragraph_indent;
+        if indent == 0 {
+          self.push_separator(index);
+        }
+        let opening;
+        let runs = if self.options.drop_caps && self.after_heading {
+          opening = opening_runs(runs);
+          &opening
+        } else {
+          runs
+        };
+        self.push_lines(index, wrap_chars(runs, width, indent), indent, false);
+        if indent == 0 {
+          self.push_separator(index);
+        }
+      }
+      RenderableBlock::Centered(runs) => {
+        self.push_separator(index);
+        self.push_lines(index, wrap_chars(runs, width, 0), 0, true);
+        self.push_separator(index);
+      }
+      RenderableBlock::Preformatted(runs) => {
+        self.push_separator(index);
+        // Lines are kept as they are, only broken where they don't fit
+        self.push_lines(index, preformatted_chars(runs, width), 0, false);
+        self.push_separator(index);
+      }
+      RenderableBlock::Code(runs) => {
+        self.push_separator(index);
+        let width = if self.options.wrap_code {
+          width
+        } else {
+          usize::MAX
+        };
+        let first = self.pending.len();
+        self.push_lines(index, preformatted_chars(runs, width), 0, false);
+        for line in self.pending.range_mut(first..) {
+          line.code = true;
+        }
+        self.push_separator(index);
+      }
+      RenderableBlock::Heading(level, runs) => {
+        self.push_separator(index);
+        // Long headings wrap like paragraphs, keeping a one-space indent
+        let runs = heading_runs(*level, runs, self.options.screen_width);
+        self.push_lines(index, wrap_chars(&runs, width, 1), 1, false);
+        self.push_separator(index);
+      }
+      image @ RenderableBlock::Image { src, alt, caption } => {
+        self.push_separator(index);
+
+        let first = self.pending.len();
+        let mut placeholder = image_placeholder_runs(
+          &self.options.image_placeholder,
+          image.image_label().unwrap_or(src),
+          alt.as_deref().unwrap_or(""),
+          src,
+        );
+        if !placeholder.is_empty() {
+          if let Some(size) = self.options.image_sizes.get(src) {
+            placeholder.push(TextRun::plain(format!(" ({})", size)));
+          }
+          self.push_lines(index, wrap_chars(&placeholder, width, 0), 0, false);
+        }
+        // The caption goes right under the image
+        if let Some(caption) = caption {
+          self.push_lines(index, wrap_chars(caption, width, 0), 0, true);
+        }
+        if self.options.image_hint {
+          let hint = [TextRun::plain(
+            "(Press 'i' when this line is visible to view the image)",
+          )];
+          self.push_lines(index, wrap_chars(&hint, width, 0), 0, false);
+        }
+
+        // Mark where the image sits, even when nothing stands in for it
+        match self.pending.get_mut(first) {
+          Some(line) => line.image = true,
+          None => self.pending.push_back(RenderedLine {
+            image: true,
+            block: index,
+            ..RenderedLine::default()
+          }),
+        }
+
+        self.push_separator(index);
+      }
+      RenderableBlock::SceneBreak => {
+        self.push_separator(index);
+        if !self.options.scene_break.is_empty() {
+          let glyph = [TextRun::plain(self.options.scene_break.clone())];
+          self.push_lines(index, wrap_chars(&glyph, width, 0), 0, true);
+          self.push_separator(index);
+        }
+      }
+      RenderableBlock::ImagePlaceholder(description) => {
+        let placeholder =
+          image_placeholder_runs(&self.options.image_placeholder, description, "", "");
+        if !placeholder.is_empty() {
+          self.push_separator(index);
+          self.push_lines(index, wrap_chars(&placeholder, width, 0), 0, false);
+          self.push_separator(index);
+        }
+      }
+    }
+  }
+
+  // Add a blank line between blocks; when collapsing, a block's leading blank
+  // line merges into the previous block's trailing one
+  fn push_separator(&mut self, block: usize) {
+    let after_blank = match self.pending.back() {
+      Some(line) => line.is_blank(),
+      None => self.after_blank,
+    };
+    if !(self.options.collapse_blank_lines && after_blank) {
+      self.pending.push_back(RenderedLine {
+        block,
+        ..RenderedLine::default()
+      });
+    }
+  }
+
+  fn push_lines(
+    &mut self,
+    block: usize,
+    lines: Vec<StyledChars>,
+    first_line_indent: usize,
+    centered: bool,
+  ) {
+    self.pending.extend(
+      rendered_lines(lines, first_line_indent)
+        .into_iter()
+        .map(|line| RenderedLine {
+          centered,
+          block,
+          ..line
+        }),
+    );
+  }
+}
+
+/// Wrap styled text into lines of at most `width` columns, the first one
+/// indented by `first_line_indent` spaces
+pub fn wrap_runs(runs: &[TextRun], width: usize, first_line_indent: usize) -> Vec<RenderedLine> {
+  rendered_lines(
+    wrap_chars(runs, width, first_line_indent),
+    first_line_indent,
+  )
+}
+
+// The runs of an opening paragraph, with its first word set in bold
+fn opening_runs(runs: &[TextRun]) -> Vec<TextRun> {
+  let mut opened = Vec::with_capacity(runs.len() + 1);
+  let mut in_word = false;
+  let mut done = false;
+  for run in runs {
+    if done {
+      opened.push(run.clone());
+      continue;
+    }
+    // The word ends at the first whitespace after some text
+    let end = run.text.char_indices().find_map(|(at, c)| {
+      if c.is_whitespace() {
+        in_word.then_some(at)
+      } else {
+        in_word = true;
+        None
+      }
+    });
+    let (word, rest) = run.text.split_at(end.unwrap_or(run.text.len()));
+    opened.push(TextRun {
+      text: word.to_string(),
+      style: TextStyle {
+        bold: true,
+        ..run.style
+      },
+    });
+    if end.is_some() {
+      done = true;
+      opened.push(TextRun {
+        text: rest.to_string(),
+        style: run.style,
+      });
+    }
+  }
+  opened
+}
+
+// Helper function to turn wrapped characters into lines, indenting the first
+fn rendered_lines(lines: Vec<StyledChars>, first_line_indent: usize) -> Vec<RenderedLine> {
+  lines
+    .into_iter()
+    .enumerate()
+    .map(|(index, chars)| {
+      // Merge neighbouring characters that share a style into one run
+      let mut runs = Vec::new();
+      let mut text = String::new();
+      let mut style = chars.first().map(|&(_, style)| style).unwrap_or_default();
+      for (c, char_style) in chars {
+        if char_style != style && !text.is_empty() {
+          runs.push(TextRun {
+            text: std::mem::take(&mut text),
+            style,
+          });
+        }
+        style = char_style;
+        text.push(c);
+      }
+      runs.push(TextRun { text, style });
+
+      RenderedLine {
+        runs,
+        indent: if index == 0 { first_line_indent } else { 0 },
+        ..RenderedLine::default()
+      }
+    })
+    .collect()
+}
+
+// Helper function to decorate a heading according to its level
+fn heading_runs(level: usize, runs: &[TextRun], screen_width: usize) -> Vec<TextRun> {
+  let (heading_prefix, heading_suffix, style) = match level.clamp(1, 6) {
+    1 => (
+      "=".repeat(std::cmp::min(5, screen_width / 4)),
+      "=".repeat(std::cmp::min(5, screen_width / 4)),
+      TextStyle {
+        bold: true,
+        ..TextStyle::default()
+      },
+    ),
+    2 => (
+      "-".repeat(std::cmp::min(3, screen_width / 6)),
+      "-".repeat(std::cmp::min(3, screen_width / 6)),
+      TextStyle {
+        bold: true,
+        ..TextStyle::default()
+      },
+    ),
+    3 => (
+      "###".to_string(),
+      "".to_string(),
+      TextStyle {
+        bold: true,
+        ..TextStyle::default()
+      },
+    ),
+    4 => (
+      "####".to_string(),
+      "".to_string(),
+      TextStyle {
+        underline: true,
+        ..TextStyle::default()
+      },
+    ),
+    5 => (
+      "#####".to_string(),
+      "".to_string(),
+      TextStyle {
+        underline: true,
+        ..TextStyle::default()
+      },
+    ),
+    _ => ("######".to_string(), "".to_string(), TextStyle::default()),
+  };
+
+  // Inline formatting adds to the heading's own style
+  let text = runs.iter().map(|run| TextRun {
+    text: run.text.clone(),
+    style: TextStyle {
+      bold: style.bold || run.style.bold,
+      italic: style.italic || run.style.italic,
+      underline: style.underline || run.style.underline,
+      link: run.style.link,
+    },
+  });
+
+  std::iter::once(TextRun {
+    text: format!("{} ", heading_prefix),
+    style,
+  })
+  .chain(text)
+  .chain(std::iter::once(TextRun {
+    text: format!(" {}", heading_suffix),
+    style,
+  }))
+  .collect()
+}
+
+// Helper function to fill in the image placeholder template, with the
+// substituted fields in italics
+fn image_placeholder_runs(template: &str, label: &str, alt: &str, src: &str) -> Vec<TextRun> {
+  let italic = TextStyle {
+    italic: true,
+    ..TextStyle::default()
+  };
+  let mut runs = Vec::new();
+  let mut rest = template;
+
+  while let Some(start) = rest.find('{') {
+    let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+      break;
+    };
+    let value = match &rest[start + 1..end] {
+      "label" => label,
+      "alt" => alt,
+      "src" => src,
+      _ => {
+        // Unknown fields are kept verbatim
+        runs.push(TextRun::plain(&rest[..=end]));
+        rest = &rest[end + 1..];
+        continue;
+      }
+    };
+    if start > 0 {
+      runs.push(TextRun::plain(&rest[..start]));
+    }
+    if !value.is_empty() {
+      runs.push(TextRun {
+        text: value.to_string(),
+        style: italic,
+      });
+    }
+    rest = &rest[end + 1..];
+  }
+  if !rest.is_empty() {
+    runs.push(TextRun::plain(rest));
+  }
+
+  runs
+}
+
+/// Invisible hyphenation point, shown only when a word is broken there
+const SOFT_HYPHEN: char = '\u{ad}';
+/// Spaces that must not break a line: no-break, narrow no-break and figure spaces
+const NON_BREAKING_SPACES: [char; 3] = ['\u{a0}', '\u{202f}', '\u{2007}'];
+
+/// A wrapped line (or a word) as styled characters
+type StyledChars = Vec<(char, TextStyle)>;
+
+/// CJK punctuation that must not start a line
+const NO_BREAK_BEFORE: &str = "、。，．：；！？）」』】〕〉》";
+/// CJK punctuation that must not end a line
+const NO_BREAK_AFTER: &str = "（「『【〔〈《";
+
+/// A stretch of characters that wraps as a unit
+#[derive(Default)]
+struct Word {
+  chars: StyledChars,
+  hyphens: Vec<usize>, // Soft hyphen positions, where the word may still break
+  spaced: bool,        // Whether whitespace separates it from the word before
+}
+
+// Helper function to wrap styled text into lines of styled characters; the
+// first line leaves room for `first_line_indent`
+fn wrap_chars(
+  runs: &[TextRun],
+  width: usize,
+  first_line_indent: usize,
+) -> Vec<Vec<(char, TextStyle)>> {
+  let width = width.max(1);
+  let first_width = width.saturating_sub(first_line_indent).max(1);
+
+  // Split the runs into words; a word may mix styles, e.g. "<em>un</em>done".
+  // Non-breaking spaces stay inside words, and soft hyphens are dropped but
+  // remembered as places where the word may be hyphenated. CJK text has no
+  // spaces, so lines may break around every ideograph instead
+  let mut words: Vec<Word> = Vec::new();
+  let mut current_word = Word::default();
+  let mut spaced = false;
+  for run in runs {
+    for c in run.text.chars() {
+      match c {
+        SOFT_HYPHEN => current_word.hyphens.push(current_word.chars.len()),
+        c if c.is_whitespace() && !NON_BREAKING_SPACES.contains(&c) => {
+          if !current_word.chars.is_empty() {
+            words.push(std::mem::take(&mut current_word));
+          }
+          spaced = true;
+        }
+        c => {
+          if let Some(&(last, _)) = current_word.chars.last()
+            && (is_cjk(last) || is_cjk(c))
+            && !NO_BREAK_AFTER.contains(last)
+            && !NO_BREAK_BEFORE.contains(c)
+          {
+            words.push(std::mem::take(&mut current_word));
+          }
+          if current_word.chars.is_empty() {
+            current_word.spaced = std::mem::take(&mut spaced);
+          }
+          let c = if NON_BREAKING_SPACES.contains(&c) {
+            ' '
+          } else {
+            c
+          };
+          current_word.chars.push((c, run.style));
+        }
+      }
+    }
+  }
+  if !current_word.chars.is_empty() {
+    words.push(current_word);
+  }
+
+  let mut lines: Vec<Vec<(char, TextStyle)>> = Vec::new();
+  let mut current_line: Vec<(char, TextStyle)> = Vec::new();
+
+  for Word {
+    chars: mut word,
+    mut hyphens,
+    spaced,
+  } in words
+  {
+    // The first line is narrower to leave room for the indent
+    let line_width = if lines.is_empty() { first_width } else { width };
+
+    // Check if adding this word would exceed the width
+    let separator = usize::from(spaced && !current_line.is_empty());
+    let needed = text_width(&current_line) + separator + text_width(&word);
+
+    if needed <= line_width {
+      push_word(&mut current_line, &mut word, spaced);
+      continue;
+    }
+
+    // Hyphenate at the last soft hyphen that still fits on the line
+    let room = line_width.saturating_sub(text_width(&current_line) + separator + 1);
+    if let Some(&at) = hyphens
+      .iter()
+      .rev()
+      .find(|&&at| at > 0 && at < word.len() && text_width(&word[..at]) <= room)
+    {
+      let rest = word.split_off(at);
+      let hyphen_style = word[at - 1].1;
+      word.push(('-', hyphen_style));
+      push_word(&mut current_line, &mut word, spaced);
+      word = rest;
+      hyphens = hyphens
+        .into_iter()
+        .filter(|&position| position > at)
+        .map(|position| position - at)
+        .collect();
+    }
+
+    // If the current line is not empty, add it to lines
+    if !current_line.is_empty() {
+      lines.push(std::mem::take(&mut current_line));
+    }
+
+    // If the word itself is longer than the line, we need to split it,
+    // preferring its soft hyphens
+    loop {
+      let line_width = if lines.is_empty() { first_width } else { width };
+      if text_width(&word) <= line_width {
+        current_line = word;
+        break;
+      }
+      let at = hyphens
+        .iter()
+        .rev()
+        .find(|&&at| at > 0 && text_width(&word[..at]) < line_width)
+        .copied();
+      let rest = match at {
+        Some(at) => {
+          let rest = word.split_off(at);
+          word.push(('-', word[at - 1].1));
+          hyphens = hyphens
+            .into_iter()
+            .filter(|&position| position > at)
+            .map(|position| position - at)
+            .collect();
+          rest
+        }
+        None => {
+          hyphens.clear();
+          // Break where the line is full, keeping at least one character
+          let mut used = 0;
+          let at = word
+            .iter()
+            .position(|&(c, _)| {
+              used += c.width().unwrap_or(0);
+              used > line_width
+            })
+            .unwrap_or(word.len())
+            .max(1);
+          word.split_off(at)
+        }
+      };
+      lines.push(word);
+      word = rest;
+    }
+  }
+
+  // Add the last line if it's not empty
+  if !current_line.is_empty() {
+    lines.push(current_line);
+  }
+
+  // If no lines were added (empty text), add an empty line
+  if lines.is_empty() {
+    lines.push(Vec::new());
+  }
+
+  lines
+}
+
+// Helper function to split preformatted text at its line breaks, breaking
+// lines that are too wide at the last column that fits
+fn preformatted_chars(runs: &[TextRun], width: usize) -> Vec<StyledChars> {
+  let width = width.max(1);
+  let mut lines = vec![Vec::new()];
+  for run in runs {
+    for c in run.text.chars() {
+      let line = lines.last_mut().expect("there is always a line");
+      match c {
+        '\n' => lines.push(Vec::new()),
+        // Tabs have no fixed width on screen
+        '\t' => line.extend([(' ', run.style); 4]),
+        c if text_width(line) + c.width().unwrap_or(0) > width => lines.push(vec![(c, run.style)]),
+        c => line.push((c, run.style)),
+      }
+    }
+  }
+  lines
+}
+
+// Helper function to measure styled characters in terminal columns
+fn text_width(chars: &[(char, TextStyle)]) -> usize {
+  chars.iter().map(|&(c, _)| c.width().unwrap_or(0)).sum()
+}
+
+// Whether a character belongs to a script written without spaces between words
+fn is_cjk(c: char) -> bool {
+  matches!(c as u32,
+    0x2E80..=0x9FFF     // Radicals, punctuation, kana and unified ideographs
+    | 0xAC00..=0xD7AF   // Hangul syllables
+    | 0xF900..=0xFAFF   // Compatibility ideographs
+    | 0xFF00..=0xFFEF   // Full-width forms
+    | 0x20000..=0x2FFFF // Supplementary ideographs
+  )
+}
+
+// Helper function to append a word to a line, separated by a space unless
+// the word follows on directly
+fn push_word(line: &mut Vec<(char, TextStyle)>, word: &mut Vec<(char, TextStyle)>, spaced: bool) {
+  if spaced && let Some(&(_, last_style)) = line.last() {
+    // The space takes the style only when both neighbours share it
+    let space_style = if word[0].1 == last_style {
+      last_style
+    } else {
+      TextStyle::default()
+    };
+    line.push((' ', space_style));
+  }
+  line.append(word);
+}
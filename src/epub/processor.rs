@@ -1,74 +1,300 @@
-use super::content::{RenderableBlock, RenderableChapter};
-use xml::reader::{EventReader, XmlEvent};
+use super::content::{Footnote, RenderableBlock, RenderableChapter, TextRun, TextStyle, runs_text};
+use super::stylesheet::{ClassStyle, Stylesheet, parse_inline_style};
+use crate::timing;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::time::Instant;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{ParserConfig, XmlEvent};
 
-pub fn process_chapter_html(html_content: &str) -> RenderableChapter {
+/// Start tags of HTML's void elements, which never have content, closed
+/// XML-style or not
+static VOID_TAG: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(
+    r"(?i)<(area|base|br|col|embed|hr|img|input|link|meta|source|track|wbr)\b([^>]*?)\s*/?>",
+  )
+  .unwrap()
+});
+/// End tags of void elements, which HTML allows but ignores
+static VOID_END_TAG: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r"(?i)</(area|base|br|col|embed|hr|img|input|link|meta|source|track|wbr)\s*>").unwrap()
+});
+
+/// HTML named entities that turn up in books, with the text they stand for
+const HTML_ENTITIES: [(&str, &str); 24] = [
+  ("nbsp", "\u{a0}"),
+  ("shy", "\u{ad}"),
+  ("ensp", "\u{2002}"),
+  ("emsp", "\u{2003}"),
+  ("thinsp", "\u{2009}"),
+  ("ndash", "–"),
+  ("mdash", "—"),
+  ("lsquo", "‘"),
+  ("rsquo", "’"),
+  ("ldquo", "“"),
+  ("rdquo", "”"),
+  ("laquo", "«"),
+  ("raquo", "»"),
+  ("hellip", "…"),
+  ("middot", "·"),
+  ("bull", "•"),
+  ("copy", "©"),
+  ("reg", "®"),
+  ("trade", "™"),
+  ("deg", "°"),
+  ("times", "×"),
+  ("sect", "§"),
+  ("para", "¶"),
+  ("dagger", "†"),
+];
+
+/// Optional transforms applied while processing a chapter
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessOptions {
+  /// Curl straight quotes and turn `--`/`---` into en/em dashes
+  pub smart_punctuation: bool,
+}
+
+/// Turn a chapter's XHTML into blocks
+///
+/// Processing only depends on its arguments. Markup that doesn't parse is shown
+/// as it is, in a single paragraph.
+pub fn process_chapter_html(
+  html_content: &str,
+  stylesheet: &Stylesheet,
+  options: &ProcessOptions,
+) -> RenderableChapter {
+  // A spine item may be an empty file, which is not an error; say so rather
+  // than show an empty page
+  if html_content.trim().is_empty() {
+    return RenderableChapter {
+      blocks: vec![RenderableBlock::Centered(vec![TextRun::plain(
+        "This page has no text content",
+      )])],
+      rtl: false,
+      footnotes: Vec::new(),
+      title: None,
+      links: Vec::new(),
+      anchors: HashMap::new(),
+    };
+  }
+
+  let started = Instant::now();
+  let chapter = process_html(html_content, stylesheet, options);
+  timing::record(started, || {
+    format!("process {} bytes of HTML", html_content.len())
+  });
+  chapter
+}
+
+fn process_html(
+  html_content: &str,
+  stylesheet: &Stylesheet,
+  options: &ProcessOptions,
+) -> RenderableChapter {
   let mut blocks = Vec::new();
-  let mut current_text = String::new();
+  let mut current_runs: Vec<TextRun> = Vec::new();
   let mut heading_level = 0;
+  // Depth of the heading element being read
+  let mut heading_depth: Option<usize> = None;
+  let mut rtl = false;
+  // Inline style of every open element, so nested formatting composes
+  let mut style_stack: Vec<TextStyle> = Vec::new();
+  // Whether each open element is centered
+  let mut center_stack: Vec<bool> = Vec::new();
+  // Number of open elements whose text must be kept verbatim
+  let mut code_depth = 0;
+  // Number of open elements whose line breaks and spacing must be kept, and
+  // whether each open element is one of them
+  let mut preserve_depth = 0;
+  let mut preserve_stack: Vec<bool> = Vec::new();
+  // Whether the preserved text being read is program code
+  let mut preserve_code = false;
+  // Notes are collected apart from the main text; the depth marks where the note ends
+  let mut footnotes = Vec::new();
+  let mut footnote: Option<(usize, Footnote)> = None;
+  // Depth of the `epub:type="chapter"` element being read, and the title found in it
+  let mut chapter_depth: Option<usize> = None;
+  let mut title = None;
+  let mut links = Vec::new();
+  let mut anchors = HashMap::new();
+  // The `<figure>` being read, and the depth of the `<figcaption>` in it
+  let mut figure: Option<Figure> = None;
+  let mut caption_depth: Option<usize> = None;
+  // Number of blocks when the current `<p>` opened, to tell an empty one
+  // from one that only held an image
+  let mut paragraph_start = 0;
+  // Number of open `<rp>` elements, whose fallback parentheses are dropped
+  let mut rp_depth = 0;
 
   // Preprocess the HTML to make it more parseable
   let processed_html = preprocess_html(html_content);
-
-  let parser = EventReader::from_str(&processed_html);
+  let parser = html_reader(&processed_html);
 
   for event in parser {
     match event {
       Ok(XmlEvent::StartElement {
         name, attributes, ..
       }) => {
-        match name.local_name.as_str() {
-          "h1" => {
-            heading_level = 1;
-            current_text.clear();
+        // The writing direction is declared on the root elements
+        if matches!(name.local_name.as_str(), "html" | "body")
+          && let Some(dir) = attributes.iter().find(|attr| attr.name.local_name == "dir")
+        {
+          rtl = dir.value.eq_ignore_ascii_case("rtl");
+        }
+
+        // An element's id points at the block its text starts in
+        if let Some(id) = attributes.iter().find(|attr| attr.name.local_name == "id") {
+          anchors.entry(id.value.clone()).or_insert(blocks.len());
+        }
+
+        if is_code_element(&name.local_name) {
+          code_depth += 1;
+        }
+        let class_styles = element_class_styles(&attributes, stylesheet);
+        let preserves = preserves_whitespace(&name.local_name, &attributes, &class_styles);
+        if preserves && preserve_depth == 0 {
+          if footnote.is_none() && heading_level == 0 {
+            // Text before the preserved block is a paragraph of its own
+            flush_paragraph(
+              &mut current_runs,
+              &mut blocks,
+              center_stack.last().copied().unwrap_or(false),
+            );
           }
-          "h2" => {
-            heading_level = 2;
-            current_text.clear();
+          preserve_code = false;
+        }
+        if preserves {
+          preserve_depth += 1;
+        }
+        preserve_stack.push(preserves);
+        // Any code element makes the whole preserved block code
+        if preserve_depth > 0 && code_depth > 0 {
+          preserve_code = true;
+        }
+
+        let depth = style_stack.len();
+        let types = epub_types(&attributes);
+        let parent_style = style_stack.last().copied().unwrap_or_default();
+        let link = match attributes
+          .iter()
+          .find(|attr| attr.name.local_name == "href")
+        {
+          Some(href) if name.local_name == "a" => {
+            links.push(decode_path(&href.value));
+            Some(links.len() - 1)
           }
-          "h3" => {
-            heading_level = 3;
-            current_text.clear();
+          _ => None,
+        };
+        style_stack.push(element_style(
+          &name.local_name,
+          link,
+          &class_styles,
+          parent_style,
+        ));
+        let parent_centered = center_stack.last().copied().unwrap_or(false);
+        center_stack.push(class_styles.iter().fold(
+          parent_centered
+            || name.local_name == "center"
+            || types.contains(&"titlepage")
+            || types.contains(&"cover"),
+          |centered, class| class.center.unwrap_or(centered),
+        ));
+
+        // Ruby readings follow their base text in parentheses of our own
+        match name.local_name.as_str() {
+          "rp" => rp_depth += 1,
+          "rt" => match &mut footnote {
+            Some((_, note)) => note.text.push('('),
+            None => push_text(&mut current_runs, "(", parent_style),
+          },
+          _ => {}
+        }
+
+        if footnote.is_some() {
+          continue;
+        }
+        if types
+          .iter()
+          .any(|kind| matches!(*kind, "footnote" | "endnote" | "rearnote"))
+        {
+          footnote = Some((
+            depth,
+            Footnote {
+              text: String::new(),
+            },
+          ));
+          continue;
+        }
+        if chapter_depth.is_none() && types.contains(&"chapter") {
+          chapter_depth = Some(depth);
+        }
+        // Preserved text only takes line breaks from its markup
+        if preserve_depth > 0 && !matches!(name.local_name.as_str(), "img" | "image") {
+          if name.local_name == "br" {
+            push_text(&mut current_runs, "\n", parent_style);
           }
-          "h4" => {
-            heading_level = 4;
-            current_text.clear();
+          continue;
+        }
+        // A caption's markup only styles its text
+        if caption_depth.is_some() {
+          continue;
+        }
+        if heading_depth.is_none()
+          && let Some(level) = element_heading_level(&name.local_name, &attributes)
+        {
+          heading_level = level;
+          heading_depth = Some(depth);
+          current_runs.clear();
+          continue;
+        }
+
+        match name.local_name.as_str() {
+          "p" => {
+            // Start of a paragraph
+            current_runs.clear();
+            paragraph_start = blocks.len();
           }
-          "h5" => {
-            heading_level = 5;
-            current_text.clear();
+          "div" | "section" | "article" if heading_level == 0 => {
+            // Text before a nested block is a paragraph of its own
+            flush_paragraph(&mut current_runs, &mut blocks, parent_centered);
           }
-          "h6" => {
-            heading_level = 6;
-            current_text.clear();
+          "figure" if heading_level == 0 => {
+            flush_paragraph(&mut current_runs, &mut blocks, parent_centered);
+            figure = Some(Figure {
+              depth,
+              image: None,
+              caption: None,
+            });
           }
-          "p" => {
-            // Start of a paragraph
-            current_text.clear();
+          "figcaption" if figure.is_some() && heading_level == 0 => {
+            flush_paragraph(&mut current_runs, &mut blocks, parent_centered);
+            caption_depth = Some(depth);
           }
-          "img" => {
-            if let Some(src_attr) = attributes.iter().find(|attr| attr.name.local_name == "src") {
-              blocks.push(RenderableBlock::Image(
-                src_attr.value.clone().replace(r"../", ""),
-              ));
-            } else {
-              blocks.push(RenderableBlock::ImagePlaceholder(
-                "Image without source".to_string(),
-              ));
+          "img" | "image" => {
+            // Text before an image in the same paragraph stays before it
+            if preserve_depth > 0 {
+              flush_preformatted(&mut current_runs, &mut blocks, preserve_code);
+            } else if heading_level == 0 {
+              flush_paragraph(&mut current_runs, &mut blocks, parent_centered);
             }
-          }
-          "image" => {
-            if let Some(src_attr) = attributes
-              .iter()
-              .find(|attr| attr.name.local_name == "href")
-            {
-              blocks.push(RenderableBlock::Image(
-                src_attr.value.clone().replace(r"../", ""),
-              ));
+            let src_attr = if name.local_name == "img" {
+              "src"
             } else {
-              blocks.push(RenderableBlock::ImagePlaceholder(
-                "Image without source".to_string(),
-              ));
+              "href"
+            };
+            let mut image = image_block(&attributes, src_attr);
+            // The figure's caption belongs to its first image
+            if let Some(figure) = &mut figure
+              && figure.image.is_none()
+              && let RenderableBlock::Image { caption, .. } = &mut image
+            {
+              *caption = figure.caption.take();
+              figure.image = Some(blocks.len());
             }
+            blocks.push(image);
           }
           _ => {
             // For other elements, we don't need special handling
@@ -76,50 +302,509 @@ pub fn process_chapter_html(html_content: &str) -> RenderableChapter {
         }
       }
       Ok(XmlEvent::EndElement { name }) => {
+        style_stack.pop();
+        let centered = center_stack.pop().unwrap_or(false);
+        if is_code_element(&name.local_name) {
+          code_depth -= 1;
+        }
+        let preserved = preserve_depth > 0;
+        if preserve_stack.pop().unwrap_or(false) {
+          preserve_depth -= 1;
+        }
         match name.local_name.as_str() {
-          "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
-            if !current_text.trim().is_empty() {
-              blocks.push(RenderableBlock::Heading(
-                heading_level,
-                current_text.trim().to_string(),
-              ));
+          "rp" => rp_depth -= 1,
+          "rt" => match &mut footnote {
+            Some((_, note)) => note.text.push(')'),
+            None => {
+              let style = style_stack.last().copied().unwrap_or_default();
+              push_text(&mut current_runs, ")", style);
+            }
+          },
+          _ => {}
+        }
+
+        if let Some((depth, _)) = &footnote {
+          if style_stack.len() == *depth
+            && let Some((_, mut note)) = footnote.take()
+          {
+            note.text = note.text.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !note.text.is_empty() {
+              footnotes.push(note);
             }
-            current_text.clear();
-            heading_level = 0;
           }
-          "p" => {
-            if !current_text.trim().is_empty() {
-              blocks.push(RenderableBlock::Paragraph(current_text.trim().to_string()));
+          continue;
+        }
+        if chapter_depth == Some(style_stack.len()) {
+          chapter_depth = None;
+        }
+        if preserved {
+          if preserve_depth == 0 {
+            flush_preformatted(&mut current_runs, &mut blocks, preserve_code);
+          } else if matches!(name.local_name.as_str(), "p" | "div") {
+            // Nested blocks, such as the lines of a stanza, end their line
+            push_text(&mut current_runs, "\n", TextStyle::default());
+          }
+          continue;
+        }
+        if caption_depth == Some(style_stack.len()) {
+          caption_depth = None;
+          let caption = take_trimmed_runs(&mut current_runs).map(|runs| {
+            runs
+              .into_iter()
+              .map(|run| TextRun {
+                style: TextStyle {
+                  italic: true,
+                  ..run.style
+                },
+                ..run
+              })
+              .collect()
+          });
+          if let Some(figure) = &mut figure {
+            // A caption above the image waits for it
+            match figure.image.and_then(|index| blocks.get_mut(index)) {
+              Some(RenderableBlock::Image { caption: slot, .. }) => *slot = caption,
+              _ => figure.caption = caption,
+            }
+          }
+          continue;
+        }
+        if caption_depth.is_some() {
+          continue;
+        }
+        if figure
+          .as_ref()
+          .is_some_and(|figure| figure.depth == style_stack.len())
+        {
+          flush_paragraph(&mut current_runs, &mut blocks, centered);
+          // Without an image to go under, the caption is a paragraph of its own
+          if let Some(caption) = figure.take().and_then(|figure| figure.caption) {
+            blocks.push(RenderableBlock::Centered(caption));
+          }
+          continue;
+        }
+
+        if heading_depth == Some(style_stack.len()) {
+          if let Some(runs) = take_trimmed_runs(&mut current_runs) {
+            if title.is_none() && chapter_depth.is_some() {
+              title = Some(runs_text(&runs));
+            }
+            blocks.push(RenderableBlock::Heading(heading_level, runs));
+          }
+          heading_level = 0;
+          heading_depth = None;
+          continue;
+        }
+
+        match name.local_name.as_str() {
+          "p" if blocks.len() == paragraph_start && is_scene_break(&current_runs) => {
+            current_runs.clear();
+            // Leading ones are only spacing, and a run of them is one break
+            if blocks
+              .last()
+              .is_some_and(|block| *block != RenderableBlock::SceneBreak)
+            {
+              blocks.push(RenderableBlock::SceneBreak);
             }
-            current_text.clear();
+          }
+          "p" => {
+            flush_paragraph(&mut current_runs, &mut blocks, centered);
+          }
+          "div" | "section" | "article" if heading_level == 0 => {
+            // Bare text in a block-level container ends with it
+            flush_paragraph(&mut current_runs, &mut blocks, centered);
           }
           _ => {
             // For other elements, we don't need special handling
           }
         }
       }
-      Ok(XmlEvent::Characters(text)) => {
-        current_text.push_str(&text);
+      Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::Whitespace(text)) => {
+        if rp_depth > 0 {
+          continue;
+        }
+        if let Some((_, note)) = &mut footnote {
+          note.text.push_str(&text);
+          continue;
+        }
+        let style = style_stack.last().copied().unwrap_or_default();
+        if options.smart_punctuation && code_depth == 0 {
+          let previous = current_runs.last().and_then(|run| run.text.chars().last());
+          push_text(&mut current_runs, &smarten(&text, previous), style);
+        } else {
+          push_text(&mut current_runs, &text, style);
+        }
       }
-      Err(e) => {
+      Err(_) => {
         // If we encounter an error, fall back to the simple approach
-        eprintln!("XML parsing error: {:?}", e);
         return fallback_processing(html_content);
       }
       _ => {}
     }
   }
 
+  // Nothing follows a break at the very end
+  if blocks.last() == Some(&RenderableBlock::SceneBreak) {
+    blocks.pop();
+  }
+
+  // A document holding nothing but notes (an endnotes section) shows them as its text
+  if blocks.is_empty() {
+    blocks = footnotes
+      .iter()
+      .map(|note| RenderableBlock::Paragraph(vec![TextRun::plain(note.text.clone())]))
+      .collect();
+  }
+
   // If we didn't find any blocks, use the fallback
   if blocks.is_empty() {
     return fallback_processing(html_content);
   }
 
-  RenderableChapter { blocks }
+  RenderableChapter {
+    blocks,
+    rtl,
+    footnotes,
+    title,
+    links,
+    anchors,
+  }
+}
+
+/// A `<figure>` being read
+struct Figure {
+  depth: usize,                  // Depth of the `<figure>` element
+  image: Option<usize>,          // Index of its first image block
+  caption: Option<Vec<TextRun>>, // A caption read before any image
+}
+
+// Whether a paragraph's text is empty or only ornaments like `* * *` and `⁂`,
+// which books use to separate scenes
+fn is_scene_break(runs: &[TextRun]) -> bool {
+  runs
+    .iter()
+    .flat_map(|run| run.text.chars())
+    .all(|c| c.is_whitespace() || "*⁂#~•·◆◇❧☙✻✽❦§".contains(c))
+}
+
+// Turn the text gathered so far into a paragraph block, if there is any
+fn flush_paragraph(runs: &mut Vec<TextRun>, blocks: &mut Vec<RenderableBlock>, centered: bool) {
+  if let Some(runs) = take_trimmed_runs(runs) {
+    blocks.push(if centered {
+      RenderableBlock::Centered(runs)
+    } else {
+      RenderableBlock::Paragraph(runs)
+    });
+  }
+}
+
+// Turn the text gathered in a preserved element into a preformatted block,
+// without the blank lines around it
+fn flush_preformatted(runs: &mut Vec<TextRun>, blocks: &mut Vec<RenderableBlock>, code: bool) {
+  let chars: Vec<(char, TextStyle)> = std::mem::take(runs)
+    .into_iter()
+    .flat_map(|run| {
+      run
+        .text
+        .chars()
+        .map(move |c| (c, run.style))
+        .collect::<Vec<_>>()
+    })
+    .collect();
+  let Some(first) = chars.iter().position(|(c, _)| !c.is_whitespace()) else {
+    return;
+  };
+  let last = chars
+    .iter()
+    .rposition(|(c, _)| !c.is_whitespace())
+    .unwrap_or(first);
+  // Keep the indentation of the first line
+  let start = chars[..first]
+    .iter()
+    .rposition(|&(c, _)| c == '\n')
+    .map_or(0, |newline| newline + 1);
+
+  let mut text = Vec::new();
+  for &(c, style) in &chars[start..=last] {
+    push_text(&mut text, c.encode_utf8(&mut [0; 4]), style);
+  }
+  blocks.push(if code {
+    RenderableBlock::Code(text)
+  } else {
+    RenderableBlock::Preformatted(text)
+  });
+}
+
+// Whether an element keeps its whitespace, like `<pre>` or `xml:space="preserve"`
+fn preserves_whitespace(
+  tag: &str,
+  attributes: &[OwnedAttribute],
+  class_styles: &[ClassStyle],
+) -> bool {
+  let css = class_styles
+    .iter()
+    .fold(None, |preserve, class| class.preserve.or(preserve));
+  css.unwrap_or(tag == "pre")
+    || attributes.iter().any(|attr| {
+      attr.name.local_name == "space"
+        && attr.name.prefix.as_deref() == Some("xml")
+        && attr.value == "preserve"
+    })
+}
+
+// Heading level of an element, from `<hN>` or `role="heading"` with its
+// `aria-level` (2 when left out), kept within the six levels HTML has
+fn element_heading_level(tag: &str, attributes: &[OwnedAttribute]) -> Option<usize> {
+  let attribute = |name: &str| {
+    attributes
+      .iter()
+      .find(|attr| attr.name.local_name == name)
+      .map(|attr| attr.value.trim())
+  };
+  let level: usize = match tag.strip_prefix('h').and_then(|level| level.parse().ok()) {
+    Some(level) => level,
+    None if attribute("role") == Some("heading") => attribute("aria-level")
+      .and_then(|level| level.parse().ok())
+      .unwrap_or(2),
+    None => return None,
+  };
+  Some(level.clamp(1, 6))
+}
+
+/// Links of the `epub:type="toc"` `<nav>` in an EPUB 3 nav document, in
+/// document order, as (nesting depth, label, href)
+///
+/// Depth 0 is the outermost list. Links are returned as written, relative to
+/// the nav document.
+pub(crate) fn nav_toc_links(html_content: &str) -> Vec<(usize, String, String)> {
+  let processed_html = preprocess_html(html_content);
+  let mut links = Vec::new();
+  let mut depth = 0;
+  // Depth of the toc `<nav>` while inside it, and how many lists are open in it
+  let mut toc_depth: Option<usize> = None;
+  let mut list_depth: usize = 0;
+  // Target and label of the link being read
+  let mut link: Option<(String, String)> = None;
+
+  for event in html_reader(&processed_html) {
+    match event {
+      Ok(XmlEvent::StartElement {
+        name, attributes, ..
+      }) => {
+        depth += 1;
+        match name.local_name.as_str() {
+          "nav" if toc_depth.is_none() && epub_types(&attributes).contains(&"toc") => {
+            toc_depth = Some(depth);
+          }
+          _ if toc_depth.is_none() => {}
+          "ol" => list_depth += 1,
+          "a" => {
+            link = attributes
+              .iter()
+              .find(|attr| attr.name.local_name == "href")
+              .map(|href| (href.value.clone(), String::new()));
+          }
+          _ => {}
+        }
+      }
+      Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::Whitespace(text)) => {
+        if let Some((_, label)) = &mut link {
+          label.push_str(&text);
+        }
+      }
+      Ok(XmlEvent::EndElement { name }) => {
+        if toc_depth == Some(depth) {
+          break;
+        }
+        depth -= 1;
+        match name.local_name.as_str() {
+          _ if toc_depth.is_none() => {}
+          "ol" => list_depth = list_depth.saturating_sub(1),
+          "a" => {
+            if let Some((href, label)) = link.take() {
+              let label = label.split_whitespace().collect::<Vec<_>>().join(" ");
+              links.push((list_depth.saturating_sub(1), label, href));
+            }
+          }
+          _ => {}
+        }
+      }
+      Err(_) => break,
+      _ => {}
+    }
+  }
+  links
+}
+
+// Reader over preprocessed XHTML
+fn html_reader(processed_html: &str) -> xml::reader::EventReader<&[u8]> {
+  // XHTML chapters often use HTML's named entities, which XML doesn't know
+  HTML_ENTITIES
+    .iter()
+    .fold(ParserConfig::new(), |config, &(name, value)| {
+      config.add_entity(name, value)
+    })
+    .create_reader(processed_html.as_bytes())
+}
+
+// Values of an element's `epub:type` attribute
+fn epub_types(attributes: &[OwnedAttribute]) -> Vec<&str> {
+  attributes
+    .iter()
+    .filter(|attr| attr.name.local_name == "type" && attr.name.prefix.as_deref() == Some("epub"))
+    .flat_map(|attr| attr.value.split_whitespace())
+    .collect()
+}
+
+// Formatting an element picks up from its CSS classes and `style` attribute,
+// in increasing order of precedence
+fn element_class_styles(attributes: &[OwnedAttribute], stylesheet: &Stylesheet) -> Vec<ClassStyle> {
+  let mut styles: Vec<ClassStyle> = attributes
+    .iter()
+    .filter(|attr| attr.name.local_name == "class")
+    .flat_map(|attr| attr.value.split_whitespace())
+    .filter_map(|class| stylesheet.class_style(class).copied())
+    .collect();
+
+  if let Some(inline) = attributes
+    .iter()
+    .find(|attr| attr.name.local_name == "style")
+  {
+    styles.push(parse_inline_style(&inline.value));
+  }
+
+  styles
+}
+
+// Inline style of an element, from its tag and CSS, on top of its parent's;
+// every open element contributes, so `<em><a>` is both italic and a link
+fn element_style(
+  tag: &str,
+  link: Option<usize>,
+  class_styles: &[ClassStyle],
+  parent: TextStyle,
+) -> TextStyle {
+  let mut style = parent;
+  style.link = link.or(style.link);
+
+  match tag {
+    "em" | "i" | "cite" | "dfn" | "var" => style.italic = true,
+    "strong" | "b" => style.bold = true,
+    "u" | "ins" => style.underline = true,
+    _ => {}
+  }
+
+  for class_style in class_styles {
+    style.bold = class_style.bold.unwrap_or(style.bold);
+    style.italic = class_style.italic.unwrap_or(style.italic);
+    style.underline = class_style.underline.unwrap_or(style.underline);
+  }
+
+  style
+}
+
+// Image block for an `<img>` or SVG `<image>`, whose source is in `src_attr`
+fn image_block(attributes: &[OwnedAttribute], src_attr: &str) -> RenderableBlock {
+  let attribute = |name: &str| {
+    attributes
+      .iter()
+      .find(|attr| attr.name.local_name == name)
+      .map(|attr| attr.value.clone())
+  };
+
+  match attribute(src_attr) {
+    Some(src) => RenderableBlock::Image {
+      src: decode_path(&src),
+      alt: attribute("alt")
+        .map(|alt| alt.trim().to_string())
+        .filter(|alt| !alt.is_empty()),
+      caption: None,
+    },
+    None => RenderableBlock::ImagePlaceholder("Image without source".to_string()),
+  }
+}
+
+// Percent-decode a reference to a file in the book (`image%20name.jpg`), since
+// the container lists its files by their plain names; web addresses are left
+// as they are
+fn decode_path(value: &str) -> String {
+  if value.contains("://") || value.starts_with("mailto:") {
+    value.to_string()
+  } else {
+    percent_decode_str(value).decode_utf8_lossy().into_owned()
+  }
+}
+
+fn is_code_element(tag: &str) -> bool {
+  matches!(tag, "pre" | "code" | "kbd" | "samp" | "tt")
+}
+
+// Curl straight quotes and replace ASCII dashes; `previous` is the character
+// before the text, which decides whether a quote opens or closes
+fn smarten(text: &str, previous: Option<char>) -> String {
+  let mut result = String::with_capacity(text.len());
+  let mut previous = previous;
+  let mut chars = text.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    let opening = previous.is_none_or(|p| p.is_whitespace() || "([{–—".contains(p));
+    let replacement = match c {
+      '"' if opening => '“',
+      '"' => '”',
+      '\'' if opening => '‘',
+      '\'' => '’',
+      '-' if chars.peek() == Some(&'-') => {
+        chars.next();
+        if chars.peek() == Some(&'-') {
+          chars.next();
+          '—'
+        } else {
+          '–'
+        }
+      }
+      c => c,
+    };
+    result.push(replacement);
+    previous = Some(replacement);
+  }
+
+  result
+}
+
+// Append text to the runs, extending the last run when the style matches
+fn push_text(runs: &mut Vec<TextRun>, text: &str, style: TextStyle) {
+  match runs.last_mut() {
+    Some(last) if last.style == style => last.text.push_str(text),
+    _ => runs.push(TextRun {
+      text: text.to_string(),
+      style,
+    }),
+  }
+}
+
+// Take the accumulated runs with surrounding whitespace removed, if any text remains
+fn take_trimmed_runs(runs: &mut Vec<TextRun>) -> Option<Vec<TextRun>> {
+  let mut runs = std::mem::take(runs);
+
+  // Drop whitespace-only runs at either end, then trim the remaining edges
+  while runs.first().is_some_and(|run| run.text.trim().is_empty()) {
+    runs.remove(0);
+  }
+  while runs.last().is_some_and(|run| run.text.trim().is_empty()) {
+    runs.pop();
+  }
+
+  let first = runs.first_mut()?;
+  first.text = first.text.trim_start().to_string();
+  let last = runs.last_mut()?;
+  last.text = last.text.trim_end().to_string();
+
+  Some(runs)
 }
 
 fn preprocess_html(html_content: &str) -> String {
-  let mut content = html_content.to_string();
+  // A byte order mark would otherwise end up in the first block's text
+  let mut content = html_content.trim_start_matches('\u{feff}').to_string();
 
   // Remove DOCTYPE declaration
   if let Some(pos) = content.find("<!DOCTYPE") {
@@ -135,7 +820,15 @@ fn preprocess_html(html_content: &str) -> String {
     }
   }
 
-  content
+  // HTML leaves void elements like `<img>` and `<br>` unclosed, which XML
+  // can't parse; close them all the XHTML way and in lower case, dropping any
+  // separate end tags
+  let content = VOID_END_TAG.replace_all(&content, "");
+  VOID_TAG
+    .replace_all(&content, |captures: &regex::Captures| {
+      format!("<{}{}/>", captures[1].to_lowercase(), &captures[2])
+    })
+    .into_owned()
 }
 
 fn fallback_processing(html_content: &str) -> RenderableChapter {
@@ -144,6 +837,13 @@ fn fallback_processing(html_content: &str) -> RenderableChapter {
 
   // Simple fallback that treats the entire content as a paragraph
   RenderableChapter {
-    blocks: vec![RenderableBlock::Paragraph(processed_content.to_string())],
+    blocks: vec![RenderableBlock::Paragraph(vec![TextRun::plain(
+      processed_content,
+    )])],
+    rtl: false,
+    footnotes: Vec::new(),
+    title: None,
+    links: Vec::new(),
+    anchors: HashMap::new(),
   }
 }
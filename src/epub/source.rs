@@ -0,0 +1,87 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A book on the local file system; a copy made from stdin or a download is
+/// deleted when this is dropped
+pub struct LocalBook {
+  path: PathBuf,
+  temporary: bool,
+}
+
+impl LocalBook {
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+}
+
+impl Drop for LocalBook {
+  fn drop(&mut self) {
+    if self.temporary {
+      let _ = std::fs::remove_file(&self.path);
+    }
+  }
+}
+
+/// Turn the book argument into a local, seekable file
+///
+/// `-` reads the book from stdin and `http(s)://` URLs are downloaded; both are
+/// buffered to a temporary file since `EpubDoc` needs to seek. Anything else is
+/// taken as a path.
+pub fn local_path(source: &Path) -> Result<LocalBook, String> {
+  let Some(source_str) = source.to_str() else {
+    return Ok(LocalBook {
+      path: source.to_path_buf(),
+      temporary: false,
+    });
+  };
+
+  if source_str == "-" {
+    let mut data = Vec::new();
+    std::io::stdin()
+      .read_to_end(&mut data)
+      .map_err(|e| format!("Failed to read EPUB from stdin: {}", e))?;
+    write_temp_book("stdin", &data)
+  } else if source_str.starts_with("http://") || source_str.starts_with("https://") {
+    let response = ureq::get(source_str)
+      .call()
+      .map_err(|e| format!("Failed to download {}: {}", source_str, e))?;
+    let mut data = Vec::new();
+    response
+      .into_reader()
+      .read_to_end(&mut data)
+      .map_err(|e| format!("Failed to download {}: {}", source_str, e))?;
+    let name = source_str
+      .rsplit('/')
+      .next()
+      .filter(|name| !name.is_empty())
+      .unwrap_or("download");
+    write_temp_book(name, &data)
+  } else {
+    Ok(LocalBook {
+      path: source.to_path_buf(),
+      temporary: false,
+    })
+  }
+}
+
+// The copy is named after its content, so the same book read again gets the
+// same path, and with it the same saved position and history entry
+fn write_temp_book(name: &str, data: &[u8]) -> Result<LocalBook, String> {
+  let mut hasher = DefaultHasher::new();
+  data.hash(&mut hasher);
+  let name = name.strip_suffix(".epub").unwrap_or(name);
+  let temp_path =
+    std::env::temp_dir().join(format!("creb-{:016x}-{}.epub", hasher.finish(), name));
+
+  let mut file = std::fs::File::create(&temp_path)
+    .map_err(|e| format!("Failed to create temporary file {:?}: {}", temp_path, e))?;
+  file
+    .write_all(data)
+    .map_err(|e| format!("Failed to write temporary file {:?}: {}", temp_path, e))?;
+
+  Ok(LocalBook {
+    path: temp_path,
+    temporary: true,
+  })
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+/// Formatting a CSS class applies; `None` means the class leaves it alone
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClassStyle {
+  pub bold: Option<bool>,
+  pub italic: Option<bool>,
+  pub underline: Option<bool>,
+  pub center: Option<bool>,
+  pub preserve: Option<bool>, // Whether `white-space` keeps line breaks and spacing
+}
+
+/// The small subset of a book's CSS that maps class names to text formatting
+#[derive(Debug, Clone, Default)]
+pub struct Stylesheet {
+  classes: HashMap<String, ClassStyle>,
+}
+
+impl Stylesheet {
+  /// Add the class rules found in a CSS document
+  ///
+  /// Only simple selectors ending in a class (`.italic`, `span.bold`) are
+  /// understood; anything with combinators or pseudo-classes is skipped.
+  pub fn add_css(&mut self, css: &str) {
+    let css = strip_comments(css);
+
+    for rule in css.split('}') {
+      let Some((selectors, declarations)) = rule.split_once('{') else {
+        continue;
+      };
+
+      let style = parse_declarations(declarations);
+      if style.bold.is_none()
+        && style.italic.is_none()
+        && style.underline.is_none()
+        && style.center.is_none()
+        && style.preserve.is_none()
+      {
+        continue;
+      }
+
+      for selector in selectors.split(',').map(str::trim) {
+        if selector.contains(|c: char| c.is_whitespace() || ">+~:[".contains(c)) {
+          continue;
+        }
+        if let Some((_, class)) = selector.rsplit_once('.')
+          && !class.is_empty()
+        {
+          let entry = self.classes.entry(class.to_string()).or_default();
+          entry.bold = style.bold.or(entry.bold);
+          entry.italic = style.italic.or(entry.italic);
+          entry.underline = style.underline.or(entry.underline);
+          entry.center = style.center.or(entry.center);
+          entry.preserve = style.preserve.or(entry.preserve);
+        }
+      }
+    }
+  }
+
+  pub fn class_style(&self, class: &str) -> Option<&ClassStyle> {
+    self.classes.get(class)
+  }
+}
+
+/// Formatting from an inline `style="..."` attribute
+pub fn parse_inline_style(style: &str) -> ClassStyle {
+  parse_declarations(style)
+}
+
+fn parse_declarations(declarations: &str) -> ClassStyle {
+  let mut style = ClassStyle::default();
+
+  for declaration in declarations.split(';') {
+    let Some((property, value)) = declaration.split_once(':') else {
+      continue;
+    };
+    let value = value
+      .trim()
+      .trim_end_matches("!important")
+      .trim()
+      .to_ascii_lowercase();
+
+    match property.trim().to_ascii_lowercase().as_str() {
+      "font-weight" => {
+        style.bold = match value.as_str() {
+          "bold" | "bolder" => Some(true),
+          "normal" | "lighter" => Some(false),
+          numeric => numeric.parse::<u16>().ok().map(|weight| weight >= 600),
+        }
+      }
+      "font-style" => {
+        style.italic = match value.as_str() {
+          "italic" | "oblique" => Some(true),
+          "normal" => Some(false),
+          _ => None,
+        }
+      }
+      "text-decoration" | "text-decoration-line" => {
+        style.underline = Some(value.contains("underline"));
+      }
+      "text-align" => {
+        style.center = Some(value == "center");
+      }
+      "white-space" => {
+        style.preserve = match value.as_str() {
+          "pre" | "pre-wrap" | "pre-line" | "break-spaces" => Some(true),
+          "normal" | "nowrap" => Some(false),
+          _ => None,
+        }
+      }
+      _ => {}
+    }
+  }
+
+  style
+}
+
+fn strip_comments(css: &str) -> String {
+  let mut result = String::with_capacity(css.len());
+  let mut rest = css;
+
+  while let Some(start) = rest.find("/*") {
+    result.push_str(&rest[..start]);
+    rest = match rest[start + 2..].find("*/") {
+      Some(end) => &rest[start + 2 + end + 2..],
+      None => "",
+    };
+  }
+  result.push_str(rest);
+
+  result
+}
@@ -1,8 +1,55 @@
-pub fn create_image_widget(
-  _image_path: &str,
-) -> Result<ratatui_image::StatefulImage<ratatui_image::protocol::StatefulProtocol>, String> {
-  // Create a StatefulImage
-  let image_widget = ratatui_image::StatefulImage::new();
+use crate::config::ImageProtocol;
+use ratatui_image::picker::{Picker, ProtocolType};
+use ratatui_image::protocol::StatefulProtocol;
+use std::path::Path;
 
-  Ok(image_widget)
+/// Ask the terminal which image protocol it speaks, or `None` when it doesn't
+/// answer and images can't be shown in it
+///
+/// A protocol other than `auto` is used even when the terminal doesn't answer.
+pub fn detect_picker(protocol: ImageProtocol) -> Option<Picker> {
+  let detected = Picker::from_query_stdio().ok();
+  let protocol_type = match protocol {
+    ImageProtocol::Auto => return detected,
+    ImageProtocol::Sixel => ProtocolType::Sixel,
+    ImageProtocol::Kitty => ProtocolType::Kitty,
+    ImageProtocol::Iterm => ProtocolType::Iterm2,
+    ImageProtocol::Halfblocks => ProtocolType::Halfblocks,
+  };
+
+  // Without an answer the font size is a guess, which only affects scaling
+  let mut picker = detected.unwrap_or_else(|| Picker::from_fontsize((8, 16)));
+  picker.set_protocol_type(protocol_type);
+  Some(picker)
+}
+
+/// Decode an image file, ready to be drawn with the terminal's protocol
+pub fn load_image(picker: &Picker, image_path: &Path) -> Result<StatefulProtocol, String> {
+  let image = image::ImageReader::open(image_path)
+    .map_err(|e| e.to_string())?
+    .decode()
+    .map_err(|e| e.to_string())?;
+  Ok(picker.new_resize_protocol(image))
+}
+
+/// Pixel dimensions and file size of an image, read from its header without
+/// decoding it; `None` for formats whose size can't be probed that way
+pub fn image_info(image_path: &Path) -> Option<String> {
+  let bytes = std::fs::metadata(image_path).ok()?.len();
+  let (width, height) = image::ImageReader::open(image_path)
+    .ok()?
+    .with_guessed_format()
+    .ok()?
+    .into_dimensions()
+    .ok()?;
+  Some(format!("{}×{}, {}", width, height, format_size(bytes)))
+}
+
+fn format_size(bytes: u64) -> String {
+  const KB: u64 = 1024;
+  match bytes {
+    0..KB => format!("{} B", bytes),
+    KB..1_048_576 => format!("{} KB", bytes.div_ceil(KB)),
+    _ => format!("{:.1} MB", bytes as f64 / (KB * KB) as f64),
+  }
 }
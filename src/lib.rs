@@ -0,0 +1,30 @@
+//! EPUB parsing for creb, usable without the terminal reader
+//!
+//! Open a book with [`EpubHandler::open`], then turn spine items into
+//! structured blocks with [`EpubHandler::chapter`]:
+//!
+//! ```no_run
+//! use creb::{EpubHandler, RenderableBlock};
+//!
+//! let mut book = EpubHandler::open("book.epub".into())?;
+//! println!("{:?} by {:?}", book.book_title(), book.book_author());
+//! for index in 0..book.get_chapter_count() {
+//!   for block in book.chapter(index)?.blocks {
+//!     if let RenderableBlock::Paragraph(runs) = block {
+//!       println!("{}", creb::runs_text(&runs));
+//!     }
+//!   }
+//! }
+//! # Ok::<(), String>(())
+//! ```
+
+pub mod epub;
+pub mod timing;
+
+pub use epub::content::{
+  Footnote, RenderableBlock, RenderableChapter, TextRun, TextStyle, runs_text,
+};
+pub use epub::handler::{EpubHandler, TocEntry};
+pub use epub::layout::{LayoutOptions, Lines, RenderedLine, wrap_runs};
+pub use epub::processor::{ProcessOptions, process_chapter_html};
+pub use epub::stylesheet::Stylesheet;
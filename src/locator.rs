@@ -0,0 +1,33 @@
+use std::fmt;
+
+/// A reading position that other tools can store and hand back: the path of a
+/// spine item inside the EPUB and a character offset into its readable text,
+/// written as `path@offset` (e.g. `OEBPS/chapter03.xhtml@1520`)
+#[derive(Debug, Clone)]
+pub struct Locator {
+  pub path: String,
+  pub offset: usize,
+}
+
+impl Locator {
+  pub fn parse(input: &str) -> Result<Self, String> {
+    let invalid = || format!("Invalid locator {:?}: expected path@offset", input);
+
+    let (path, offset) = input.rsplit_once('@').ok_or_else(invalid)?;
+    if path.is_empty() {
+      return Err(invalid());
+    }
+    let offset = offset.parse().map_err(|_| invalid())?;
+
+    Ok(Locator {
+      path: path.to_string(),
+      offset,
+    })
+  }
+}
+
+impl fmt::Display for Locator {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}@{}", self.path, self.offset)
+  }
+}
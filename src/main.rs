@@ -1,47 +1,117 @@
 use clap::Parser;
-use std::io;
 
 mod app;
-mod epub;
+mod command;
+mod config;
 mod image_handler;
+mod locator;
 mod parser;
+mod persistence;
 mod reader;
 mod ui;
 
-use crate::app::AppState;
+use creb::{epub, timing};
+
+use crate::app::{AppState, DebugView, OverlayKind, PromptKind};
+use crate::config::{Config, Theme};
+use crate::epub::content::runs_text;
 use crate::epub::handler::EpubHandler;
-use crate::image_handler::create_image_widget;
+use crate::epub::layout::LayoutOptions;
+use crate::epub::source::{self, LocalBook};
+use crate::image_handler::detect_picker;
 use crate::parser::CliArgs;
+use crate::persistence::RecentBooks;
 use crate::reader::renderer::Renderer;
+use crate::reader::theme::Palette;
 use crate::ui::{UI, UserAction};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+use unicode_width::UnicodeWidthStr;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
   let args = CliArgs::parse();
+  if args.verbose
+    && let Err(e) = timing::enable()
+  {
+    eprintln!("creb: Timings won't be logged: {}", e);
+  }
+  if args.recent {
+    print_to_stdout(print_recent_books);
+    return Ok(());
+  }
+  if args.list_chapters {
+    print_to_stdout(|out| list_chapters(&args, out));
+    return Ok(());
+  }
+  if args.export_text {
+    print_to_stdout(|out| export_text(&args, out));
+    return Ok(());
+  }
+  let print_position = args.print_position;
 
-  // Initialize the EPUB handler
-  let epub_handler =
-    EpubHandler::new(args.filename).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+  // Failures before the terminal is taken over are reported plainly
+  let (_book, mut app_state) = match open_book(args) {
+    Ok(opened) => opened,
+    Err(e) => {
+      eprintln!("creb: {}", e);
+      std::process::exit(1);
+    }
+  };
 
-  // Initialize application state
-  let mut app_state = AppState::new(epub_handler, args.chapter.unwrap_or(0))
-    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+  if print_position {
+    println!("{}", app_state.locator());
+    return Ok(());
+  }
 
   // Initialize UI
   let mut ui = UI::new()?;
   ui.init()?;
+  app_state.palette = palette(app_state.config.theme, &mut ui);
+  // The terminal is only asked once; everything that shows images shares the answer
+  app_state.image_picker = detect_picker(app_state.config.image_protocol);
 
   // Main application loop
   loop {
+    app_state.reset_code_scroll_out_of_view();
+
     // Render the UI
-    ui.draw(|frame| {
-      Renderer::render_chapter(
-        frame,
-        &app_state.renderable_chapter,
-        &app_state.get_chapter_title(),
-        app_state.get_chapter_progress(),
-        app_state.scroll_position,
-      );
-    })?;
+    let mut chapter_layout = None;
+    let started = Instant::now();
+    if app_state.image_view.is_some() {
+      let title = app_state.get_chapter_title();
+      let progress = app_state.get_chapter_progress();
+      let scroll_position = app_state.scroll_position;
+      if let Some(view) = &mut app_state.image_view {
+        ui.draw(|frame| {
+          Renderer::render_image(frame, view, &title, progress, scroll_position);
+        })?;
+      }
+    } else {
+      ui.draw(|frame| {
+        chapter_layout = Renderer::render_chapter(frame, &app_state);
+      })?;
+    }
+    timing::record(started, || {
+      let kind = if chapter_layout.is_some() {
+        "layout and render"
+      } else {
+        "render"
+      };
+      format!("{} chapter {}", kind, app_state.current_chapter_index)
+    });
+    if let Some(layout) = chapter_layout {
+      app_state.chapter_layout = layout;
+      // A jump that needed the chapter's length is ready to finish now
+      if app_state.apply_pending_scroll() {
+        continue;
+      }
+    }
+
+    // Pick up chapters processed in the background
+    app_state.receive_prefetched();
+    app_state.reap_speech();
+    app_state.receive_lookup();
 
     // Check if we should quit
     if app_state.should_quit {
@@ -49,16 +119,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Handle user input
-    if let Some(action) = ui.handle_events()? {
+    if let Some(action) = ui.handle_events(app_state.input_mode(), &app_state.keymap)? {
+      // Without a footer, messages only stay up until the next key
+      if !app_state.show_footer {
+        app_state.status_message = None;
+      }
       match action {
         UserAction::Quit => {
-          app_state.should_quit = true;
+          app_state.request_quit();
+        }
+        // Code too wide for the screen takes the sideways keys until it is
+        // scrolled as far as it goes
+        UserAction::NextChapter if app_state.can_scroll_code(true) => {
+          app_state.scroll_code(true);
         }
-        UserAction::NextChapter => {
-          app_state.next_chapter()?;
+        UserAction::PreviousChapter if app_state.can_scroll_code(false) => {
+          app_state.scroll_code(false);
         }
-        UserAction::PreviousChapter => {
-          app_state.previous_chapter()?;
+        // Right-to-left books advance when turning towards the left
+        UserAction::NextChapter | UserAction::PreviousChapter => {
+          let right = matches!(action, UserAction::NextChapter);
+          if right == app_state.right_turns_forward() {
+            app_state.next_chapter()?;
+          } else {
+            app_state.previous_chapter()?;
+          }
         }
         UserAction::ScrollDown => {
           app_state.scroll_down();
@@ -67,62 +152,340 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
           app_state.scroll_up();
         }
         UserAction::PageDown => {
-          let page_size = (ui.size().height / 2) as usize;
-          app_state.page_down(page_size);
+          app_state.page_down();
         }
         UserAction::PageUp => {
-          let page_size = (ui.size().height / 2) as usize;
-          app_state.page_up(page_size);
+          app_state.page_up();
+        }
+        UserAction::NextLink => {
+          app_state.select_link(true);
+        }
+        UserAction::PreviousLink => {
+          app_state.select_link(false);
+        }
+        UserAction::FollowLink => {
+          app_state.follow_link()?;
+        }
+        UserAction::ShowPosition => {
+          app_state.status_message = Some(format!("Position: {}", app_state.locator()));
+        }
+        UserAction::ReloadBook => {
+          app_state.reload_book();
+        }
+        UserAction::CycleDebugView => {
+          app_state.cycle_debug_view();
+        }
+        UserAction::ToggleImages => {
+          app_state.toggle_images();
+        }
+        UserAction::ToggleLineNumbers => {
+          app_state.toggle_line_numbers();
+        }
+        UserAction::CycleBars => {
+          app_state.cycle_bars();
+        }
+        UserAction::ToggleOutline => {
+          app_state.toggle_outline();
+        }
+        UserAction::ShowOutline => {
+          app_state.show_overlay(OverlayKind::Outline);
+        }
+        UserAction::NextHeading => {
+          app_state.jump_to_heading(true)?;
+        }
+        UserAction::PreviousHeading => {
+          app_state.jump_to_heading(false)?;
+        }
+        UserAction::ShowToc => {
+          app_state.show_overlay(OverlayKind::Toc);
+        }
+        UserAction::AddBookmark => {
+          app_state.start_prompt(PromptKind::Bookmark);
+        }
+        UserAction::ShowBookmarks => {
+          app_state.show_overlay(OverlayKind::Bookmarks);
+        }
+        UserAction::AddAnnotation => {
+          app_state.start_prompt(PromptKind::Annotation);
+        }
+        UserAction::ShowAnnotations => {
+          app_state.show_overlay(OverlayKind::Annotations);
+        }
+        UserAction::ToggleHighlight => {
+          app_state.toggle_highlight();
+        }
+        UserAction::ShowHighlights => {
+          app_state.show_overlay(OverlayKind::Highlights);
+        }
+        UserAction::NextWord => {
+          app_state.move_word_cursor(true);
+        }
+        UserAction::PreviousWord => {
+          app_state.move_word_cursor(false);
+        }
+        UserAction::LookupWord => {
+          app_state.lookup_word();
+        }
+        UserAction::Speak => {
+          app_state.speak_from_here();
+        }
+        UserAction::StopSpeaking => {
+          app_state.stop_speaking();
+        }
+        UserAction::ShowDiagnostics => {
+          app_state.show_overlay(OverlayKind::Diagnostics);
+        }
+        UserAction::ShowFootnotes => {
+          app_state.show_overlay(OverlayKind::Footnotes);
+        }
+        UserAction::ShowStats => {
+          app_state.show_overlay(OverlayKind::Stats);
+        }
+        UserAction::FindChapter => {
+          app_state.show_overlay(OverlayKind::ChapterFinder(String::new()));
+        }
+        UserAction::StartCommand => {
+          app_state.start_prompt(PromptKind::Command);
+        }
+        UserAction::Search => {
+          app_state.start_prompt(PromptKind::Search { regex: false });
+        }
+        UserAction::NextMatch => {
+          app_state.find_match(true, false);
+        }
+        UserAction::PreviousMatch => {
+          app_state.find_match(false, false);
+        }
+        UserAction::PromptInput(c) => {
+          app_state.prompt_input(c);
+        }
+        UserAction::PromptBackspace => {
+          app_state.prompt_backspace();
+        }
+        UserAction::PromptCursor(to) => {
+          app_state.prompt_cursor(to);
+        }
+        UserAction::PromptPaste(text) => {
+          app_state.prompt_paste(&text);
+        }
+        UserAction::TogglePromptMode => {
+          app_state.toggle_prompt_mode();
+        }
+        UserAction::SelectNext => {
+          app_state.select_next();
+        }
+        UserAction::SelectPrevious => {
+          app_state.select_previous();
+        }
+        UserAction::DeleteSelected => {
+          app_state.delete_selected();
+        }
+        UserAction::Confirm => match app_state.take_fallback_image() {
+          Some(image_path) => {
+            let viewer = app_state.config.fallback_image_viewer.clone();
+            open_in_viewer(&mut ui, &mut app_state, &viewer, &image_path)?;
+          }
+          None => app_state.confirm()?,
+        },
+        UserAction::Cancel => {
+          app_state.cancel();
         }
         UserAction::ViewImage => {
-          // Display the current image if there is one
-          if let Some(image_path) = app_state.get_current_image_path() {
-            if !image_path.as_os_str().is_empty() {
-              // Convert PathBuf to string for create_image_widget function
-              if let Some(path_str) = image_path.to_str() {
-                // Try to create the image widget
-                match create_image_widget(path_str) {
-                  Ok(_image_widget) => {
-                    // In a full implementation, we would render the image widget
-                    // For now, we'll just show a message
-                    // ui.clear_screen()?;
-                    ui.draw(|frame| {
-                      Renderer::render_image(
-                        frame,
-                        path_str,
-                        &app_state.get_chapter_title(),
-                        app_state.get_chapter_progress(),
-                        app_state.scroll_position,
-                      );
-                    })?;
-                    let _ = ratatui::crossterm::event::read();
-                    // Reinitialize the terminal
-                    // ui.init()?;
-                  }
-                  Err(e) => {
-                    ui.clear_screen()?;
-                    println!("Error creating image widget: {}", e);
-                    println!("Press any key to continue...");
-                    let _ = ratatui::crossterm::event::read();
-                    ui.init()?;
-                  }
-                }
-              } else {
-                ui.clear_screen()?;
-                println!("Error: Invalid image path");
-                println!("Press any key to continue...");
-                let _ = ratatui::crossterm::event::read();
-                ui.init()?;
-              }
+          // Display the image nearest the current scroll position
+          app_state.select_image_near_scroll();
+          let image_path = app_state
+            .get_current_image_path()
+            .filter(|path| !path.as_os_str().is_empty())
+            .cloned();
+          match image_path {
+            // Keep the placeholder rather than drawing garbage, and say why
+            Some(image_path) if !app_state.can_show_images() => {
+              app_state.show_overlay(OverlayKind::ImageUnsupported(image_path));
+            }
+            Some(image_path) if !app_state.config.image_viewer.is_empty() => {
+              let viewer = app_state.config.image_viewer.clone();
+              open_in_viewer(&mut ui, &mut app_state, &viewer, &image_path)?;
+            }
+            Some(image_path) => app_state.show_image(image_path),
+            None if !app_state.can_show_images() => {
+              app_state.status_message =
+                Some("Image display not supported in this terminal".to_string());
             }
+            None => {}
           }
         }
       }
+      if app_state.take_theme_change() {
+        app_state.palette = palette(app_state.config.theme, &mut ui);
+      }
     }
   }
 
+  app_state.stop_speaking();
+  let saved = app_state.save_last_position();
+  let logged = app_state.log_session_stats();
+
   // Restore terminal
   ui.restore()?;
 
+  if let Err(e) = saved {
+    eprintln!("creb: Failed to save the reading position: {}", e);
+  }
+  if let Err(e) = logged {
+    eprintln!("creb: Failed to log reading statistics: {}", e);
+  }
+
   Ok(())
 }
+
+/// Load the config and the book, without touching the terminal; the book has
+/// to be kept until the reader exits
+fn open_book(args: CliArgs) -> Result<(LocalBook, AppState), String> {
+  let config = Config::load(&args)?;
+  let book = local_book(&args)?;
+  let epub_handler = EpubHandler::open(book.path().to_path_buf())?;
+  let chapter = match &args.chapter_file {
+    Some(href) => Some(
+      epub_handler
+        .find_spine_item(href)
+        .ok_or_else(|| format!("No chapter {:?} in the book's spine", href))?,
+    ),
+    None => args.chapter,
+  };
+  let mut app_state = AppState::new(epub_handler, chapter, config)?;
+  if let Some(locator) = &args.position {
+    app_state.go_to_locator(locator)?;
+  }
+  // A width given on the command line wins over one saved for the book
+  if let Some(max_width) = args.max_width {
+    app_state.config.max_width = max_width;
+  }
+  if args.debug_html {
+    app_state.set_debug_view(Some(DebugView::Html));
+  }
+  Ok((book, app_state))
+}
+
+/// Colors for a theme, asking the terminal for its background when it has to
+fn palette(theme: Theme, ui: &mut UI) -> Palette {
+  match theme {
+    Theme::Dark => Palette::DARK,
+    Theme::Light => Palette::LIGHT,
+    Theme::Auto => ui
+      .query_background()
+      .map_or(Palette::DARK, Palette::for_background),
+  }
+}
+
+/// The book to open, as a local file
+fn local_book(args: &CliArgs) -> Result<LocalBook, String> {
+  let filename = args
+    .filename
+    .as_deref()
+    .ok_or_else(|| "No book given".to_string())?;
+  source::local_path(filename)
+}
+
+/// Run one of the printing commands, exiting with an error if it fails. Output
+/// that stops being read, as with `creb --export-text book.epub | head`, isn't
+/// a failure.
+fn print_to_stdout(print: impl FnOnce(&mut io::StdoutLock<'static>) -> io::Result<()>) {
+  match print(&mut io::stdout().lock()) {
+    Ok(()) => {}
+    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+    Err(e) => {
+      eprintln!("creb: {}", e);
+      std::process::exit(1);
+    }
+  }
+}
+
+/// Print the reading history as `path<TAB>title<TAB>progress<TAB>last read`
+fn print_recent_books(out: &mut impl Write) -> io::Result<()> {
+  for book in RecentBooks::load().books {
+    let last_read = chrono::DateTime::from_timestamp(book.last_read, 0)
+      .map(|time| {
+        time
+          .with_timezone(&chrono::Local)
+          .format("%Y-%m-%d %H:%M")
+          .to_string()
+      })
+      .unwrap_or_default();
+    writeln!(
+      out,
+      "{}\t{}\t{:.0}%\t{}",
+      book.path.display(),
+      book.title.unwrap_or_default(),
+      book.progress * 100.0,
+      last_read
+    )?;
+  }
+  Ok(())
+}
+
+/// Hand the terminal over to an external image viewer until it exits
+fn open_in_viewer(
+  ui: &mut UI,
+  app_state: &mut AppState,
+  viewer: &str,
+  image_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+  ui.restore()?;
+  app_state.open_in_viewer(viewer, image_path);
+  ui.init()?;
+  Ok(())
+}
+
+/// Print the book laid out at `--width` columns, one chapter after another,
+/// so the output doesn't depend on the terminal
+fn export_text(args: &CliArgs, out: &mut impl Write) -> io::Result<()> {
+  let (_book, mut epub_handler) = open_for_printing(args)?;
+  let options = LayoutOptions {
+    wrap_code: true,
+    screen_width: args.width,
+    ..LayoutOptions::default()
+  };
+
+  for index in 0..epub_handler.get_chapter_count() {
+    if epub_handler.is_navigation(index) {
+      continue;
+    }
+    let chapter = epub_handler.chapter(index).map_err(io::Error::other)?;
+    for line in chapter.lines(args.width, &options) {
+      let text = runs_text(&line.runs);
+      let indent = if line.centered {
+        args.width.saturating_sub(text.width()) / 2
+      } else {
+        line.indent
+      };
+      writeln!(out, "{}{}", " ".repeat(indent), text.trim_end())?;
+    }
+  }
+  Ok(())
+}
+
+/// Print every spine index with its title, for finding what to pass to `--chapter`
+fn list_chapters(args: &CliArgs, out: &mut impl Write) -> io::Result<()> {
+  let (_book, mut epub_handler) = open_for_printing(args)?;
+  for index in 0..epub_handler.get_chapter_count() {
+    let title = match epub_handler.chapter_title(index) {
+      Some(title) => title.to_string(),
+      None => epub_handler
+        .chapter(index)
+        .ok()
+        .and_then(|chapter| chapter.title)
+        .unwrap_or_else(|| format!("Chapter {}", index + 1)),
+    };
+    // One line per chapter, whatever whitespace the title holds
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    writeln!(out, "{}\t{}", index, title)?;
+  }
+  Ok(())
+}
+
+/// Open the book for one of the printing commands
+fn open_for_printing(args: &CliArgs) -> io::Result<(LocalBook, EpubHandler)> {
+  let book = local_book(args).map_err(io::Error::other)?;
+  let epub_handler = EpubHandler::open(book.path().to_path_buf()).map_err(io::Error::other)?;
+  Ok((book, epub_handler))
+}
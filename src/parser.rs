@@ -1,3 +1,5 @@
+use crate::config::{ImageProtocol, ProgressMode, TextDirection};
+use crate::locator::Locator;
 use clap::Parser;
 use std::path::PathBuf;
 
@@ -6,14 +8,91 @@ use std::path::PathBuf;
 #[command(about = "A minimal EPUB reader for the terminal")]
 #[command(version = "0.1.0")]
 pub struct CliArgs {
-  /// EPUB file to open
-  pub filename: PathBuf,
+  /// EPUB file to open, `-` to read it from stdin, or an http(s) URL
+  #[arg(required_unless_present = "recent")]
+  pub filename: Option<PathBuf>,
 
-  /// Start at specific chapter (0-indexed)
+  /// Start at specific chapter (0-indexed) instead of where reading left off
   #[arg(short, long)]
   pub chapter: Option<usize>,
 
-  /// Enable verbose output
+  /// Start at the chapter with this path inside the EPUB (e.g. `text/ch05.xhtml`)
+  #[arg(long, conflicts_with_all = ["chapter", "position"])]
+  pub chapter_file: Option<String>,
+
+  /// Start at a `path@offset` locator, as printed by `--print-position`
+  #[arg(long, value_parser = Locator::parse, conflicts_with = "chapter")]
+  pub position: Option<Locator>,
+
+  /// How overall progress is measured (overrides the config file)
+  #[arg(long, value_enum)]
+  pub progress_mode: Option<ProgressMode>,
+
+  /// Text direction (overrides the book's own declaration)
+  #[arg(long, value_enum)]
+  pub direction: Option<TextDirection>,
+
+  /// Wrap text at no more than this many columns, 0 for the full width
+  /// (overrides the config file)
+  #[arg(long)]
+  pub max_width: Option<usize>,
+
+  /// Graphics protocol for images (overrides the config file and detection)
+  #[arg(long, value_enum)]
+  pub image_protocol: Option<ImageProtocol>,
+
+  /// Reading speed in words per minute for the time left estimate (overrides
+  /// the config file); only shown when `footer_format` has `{time_left}`
+  #[arg(long)]
+  pub wpm: Option<usize>,
+
+  /// Print where reading would start, as a `path@offset` locator, and exit
+  #[arg(long)]
+  pub print_position: bool,
+
+  /// Print the books read lately, most recent first, and exit
+  #[arg(long)]
+  pub recent: bool,
+
+  /// Print each chapter's index and title, tab-separated, and exit
+  #[arg(long)]
+  pub list_chapters: bool,
+
+  /// Print the whole book as wrapped plain text, and exit
+  #[arg(long)]
+  pub export_text: bool,
+
+  /// Columns to wrap exported text at, whatever the terminal's width
+  #[arg(long, default_value_t = 80, value_parser = parse_width, requires = "export_text")]
+  pub width: usize,
+
+  /// Path to an alternative config file
+  #[arg(long)]
+  pub config: Option<PathBuf>,
+
+  /// Show each chapter's raw HTML instead of the formatted text (Ctrl-h cycles
+  /// between the formatted text, the parsed blocks and the HTML)
+  #[arg(long)]
+  pub debug_html: bool,
+
+  /// Log how long opening, processing and drawing take to
+  /// `$XDG_CACHE_HOME/creb/timing.log`
   #[arg(short, long)]
   pub verbose: bool,
 }
+
+/// Narrowest text `--width` accepts; headings and indents need some room
+const MIN_WIDTH: usize = 20;
+
+fn parse_width(input: &str) -> Result<usize, String> {
+  let width: usize = input
+    .parse()
+    .map_err(|_| format!("Invalid width {:?}: expected a number of columns", input))?;
+  if width < MIN_WIDTH {
+    return Err(format!(
+      "Width {} is too narrow, the least is {}",
+      width, MIN_WIDTH
+    ));
+  }
+  Ok(width)
+}
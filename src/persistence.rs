@@ -0,0 +1,226 @@
+use crate::config::Theme;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A note attached to a reading position
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+  pub chapter: usize,
+  pub scroll: usize,
+  pub note: String,
+}
+
+/// Where the reader is in a book
+///
+/// The offset into the chapter is a fraction of its content rather than a line
+/// number, so it still points at the same text when the terminal width changes.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Position {
+  pub chapter: usize,
+  #[serde(default)]
+  pub fraction: f64,
+}
+
+impl Position {
+  /// Whether this position is further into the book than another
+  pub fn is_past(&self, other: &Position) -> bool {
+    (self.chapter, self.fraction) > (other.chapter, other.fraction)
+  }
+}
+
+/// A position the reader saved under a name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+  pub name: String,
+  pub chapter: usize,
+  pub scroll: usize,
+}
+
+/// A highlighted range of content lines within a chapter (inclusive)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Highlight {
+  pub chapter: usize,
+  pub start_line: usize,
+  pub end_line: usize,
+}
+
+impl Highlight {
+  pub fn contains(&self, chapter: usize, line: usize) -> bool {
+    self.chapter == chapter && (self.start_line..=self.end_line).contains(&line)
+  }
+}
+
+/// Display settings chosen for a single book, each overriding the config file
+/// when set
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+  pub paragraph_indent: Option<usize>,
+  pub line_numbers: Option<bool>,
+  pub show_images: Option<bool>,
+  pub theme: Option<Theme>,
+  pub max_width: Option<usize>,
+}
+
+/// Everything creb remembers about a single book, stored as JSON
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BookState {
+  /// Saved automatically on quit and restored on the next start
+  pub last_position: Option<Position>,
+  /// The furthest the reader has got, which going back never lowers
+  pub furthest_position: Option<Position>,
+  pub bookmarks: Vec<Bookmark>,
+  pub annotations: Vec<Annotation>,
+  pub highlights: Vec<Highlight>,
+  pub preferences: Preferences,
+}
+
+impl BookState {
+  /// Load the saved state for a book, starting fresh if there is none
+  pub fn load(book_path: &Path) -> Self {
+    let Some(state_path) = state_file_path(book_path) else {
+      return BookState::default();
+    };
+
+    match std::fs::read_to_string(&state_path) {
+      Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+        eprintln!("Warning: Ignoring unreadable state {:?}: {}", state_path, e);
+        BookState::default()
+      }),
+      Err(_) => BookState::default(),
+    }
+  }
+
+  pub fn save(&self, book_path: &Path) -> Result<(), String> {
+    let state_path =
+      state_file_path(book_path).ok_or_else(|| "No data directory available".to_string())?;
+
+    if let Some(parent) = state_path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| format!("Failed to create state directory: {}", e))?;
+    }
+
+    let json = serde_json::to_string_pretty(self)
+      .map_err(|e| format!("Failed to serialize book state: {}", e))?;
+    std::fs::write(&state_path, json).map_err(|e| format!("Failed to write book state: {}", e))
+  }
+}
+
+/// How many books the reading history keeps
+const RECENT_LIMIT: usize = 20;
+
+/// A book in the reading history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentBook {
+  pub path: PathBuf, // Absolute path of the EPUB file
+  pub title: Option<String>,
+  pub progress: f64,  // Overall progress when it was closed, from 0 to 1
+  pub last_read: i64, // Unix time when it was closed
+}
+
+/// The books read lately, most recent first, stored as JSON
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecentBooks {
+  pub books: Vec<RecentBook>,
+}
+
+impl RecentBooks {
+  /// Load the reading history, leaving out books whose files are gone
+  pub fn load() -> Self {
+    let mut recent: RecentBooks = recent_file_path()
+      .and_then(|path| std::fs::read_to_string(path).ok())
+      .and_then(|raw| serde_json::from_str(&raw).ok())
+      .unwrap_or_default();
+    recent.books.retain(|book| book.path.is_file());
+    recent
+  }
+
+  /// Put a book at the top of the reading history and save it
+  pub fn record(book: RecentBook) -> Result<(), String> {
+    // Books read from stdin or a URL only exist as temporary copies
+    if book.path.starts_with(std::env::temp_dir()) {
+      return Ok(());
+    }
+
+    let mut recent = RecentBooks::load();
+    recent.books.retain(|other| other.path != book.path);
+    recent.books.insert(0, book);
+    recent.books.truncate(RECENT_LIMIT);
+    recent.save()
+  }
+
+  fn save(&self) -> Result<(), String> {
+    let path = recent_file_path().ok_or_else(|| "No data directory available".to_string())?;
+
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)
+        .map_err(|e| format!("Failed to create state directory: {}", e))?;
+    }
+
+    let json = serde_json::to_string_pretty(self)
+      .map_err(|e| format!("Failed to serialize reading history: {}", e))?;
+    std::fs::write(&path, json).map_err(|e| format!("Failed to write reading history: {}", e))
+  }
+}
+
+/// `$XDG_DATA_HOME/creb/recent.json`
+fn recent_file_path() -> Option<PathBuf> {
+  dirs::data_dir().map(|dir| dir.join("creb").join("recent.json"))
+}
+
+/// Add a line to the book's statistics log, creating it if needed
+pub fn append_stats_log(book_path: &Path, line: &str) -> Result<(), String> {
+  let path = stats_file_path(book_path).ok_or_else(|| "No data directory available".to_string())?;
+
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)
+      .map_err(|e| format!("Failed to create stats directory: {}", e))?;
+  }
+
+  let mut file = std::fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&path)
+    .map_err(|e| format!("Failed to open stats log: {}", e))?;
+  writeln!(file, "{}", line).map_err(|e| format!("Failed to write stats log: {}", e))
+}
+
+/// `$XDG_DATA_HOME/creb/books/<flattened absolute book path>.json`
+fn state_file_path(book_path: &Path) -> Option<PathBuf> {
+  dirs::data_dir().map(|dir| {
+    dir
+      .join("creb")
+      .join("books")
+      .join(format!("{}.json", flattened_name(book_path)))
+  })
+}
+
+/// `$XDG_DATA_HOME/creb/stats/<flattened absolute book path>.log`
+fn stats_file_path(book_path: &Path) -> Option<PathBuf> {
+  dirs::data_dir().map(|dir| {
+    dir
+      .join("creb")
+      .join("stats")
+      .join(format!("{}.log", flattened_name(book_path)))
+  })
+}
+
+// The book's absolute path made into a single file name
+fn flattened_name(book_path: &Path) -> String {
+  let absolute = std::fs::canonicalize(book_path).unwrap_or_else(|_| book_path.to_path_buf());
+  let file_name: String = absolute
+    .to_string_lossy()
+    .chars()
+    .map(|c| {
+      if c.is_alphanumeric() || c == '.' {
+        c
+      } else {
+        '_'
+      }
+    })
+    .collect();
+  file_name.trim_start_matches('_').to_string()
+}
@@ -1,2 +1,3 @@
 pub mod renderer;
-
+pub mod search;
+pub mod theme;
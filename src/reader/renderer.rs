@@ -1,175 +1,329 @@
-use crate::epub::content::{RenderableBlock, RenderableChapter};
+use crate::app::{AppState, ImageView, OverlayKind, Prompt};
+use crate::epub::content::{RenderableBlock, TextRun, TextStyle};
+use crate::epub::layout::{LayoutOptions, RenderedLine, wrap_runs};
+use crate::reader::theme::Palette;
 use ratatui::{
-  layout::{Constraint, Direction, Layout},
+  layout::{Alignment, Constraint, Direction, Layout, Rect},
   style::{Modifier, Style},
   text::{Line, Span},
-  widgets::{Block, Borders, Paragraph, Wrap},
+  widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
 };
-use ratatui_image::{StatefulImage, picker::Picker};
+use ratatui_image::StatefulImage;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Narrowest screen that still gets the outline sidebar
+const OUTLINE_MIN_SCREEN_WIDTH: u16 = 100;
+/// Widest the outline sidebar gets
+const OUTLINE_MAX_WIDTH: u16 = 32;
 
 pub struct Renderer;
 
+/// Line offsets of notable blocks, recorded while laying out a chapter
+#[derive(Debug, Default)]
+pub struct ChapterLayout {
+  /// Every wrapped content line, before per-frame highlighting
+  lines: Vec<Line<'static>>,
+  /// What the lines were laid out for
+  key: Option<LayoutKey>,
+  /// Offset of each `RenderableBlock::Image` line, in block order
+  pub image_offsets: Vec<usize>,
+  /// Total number of content lines in the chapter
+  pub total_lines: usize,
+  /// Plain text of every content line, for word selection
+  pub line_texts: Vec<String>,
+  /// Number of content lines that fit on screen
+  pub viewport_height: usize,
+  /// First content line of each block, in block order
+  pub block_offsets: Vec<usize>,
+  /// Every link on screen, in reading order
+  pub links: Vec<LinkSpan>,
+  /// Content lines of code blocks, with how many columns each is wider than
+  /// the screen
+  pub code_lines: Vec<(usize, usize)>,
+}
+
+/// Everything the wrapped lines depend on, to tell when they must be redone
+#[derive(Debug, PartialEq, Eq)]
+struct LayoutKey {
+  content_version: u64,
+  wrap_width: usize,
+  screen_width: u16,
+  viewport_height: usize,
+}
+
+/// Where a link was laid out: characters `start..end` of a content line
+#[derive(Debug, Clone, Copy)]
+pub struct LinkSpan {
+  pub line: usize,
+  pub start: usize,
+  pub end: usize,
+  pub link: usize, // Index into the chapter's links
+}
+
+impl ChapterLayout {
+  /// Index of the block shown at a content line
+  pub fn block_at_line(&self, line: usize) -> usize {
+    self
+      .block_offsets
+      .partition_point(|&offset| offset <= line)
+      .saturating_sub(1)
+  }
+}
+
 impl Renderer {
-  pub fn render_chapter(
-    frame: &mut ratatui::Frame,
-    chapter: &RenderableChapter,
-    title: &str,
-    progress: f64,
-    scroll_position: usize,
-  ) {
+  /// Draw the reading view, returning the chapter's layout when it had to be
+  /// redone
+  pub fn render_chapter(frame: &mut ratatui::Frame, app_state: &AppState) -> Option<ChapterLayout> {
+    let title = header_text(app_state);
+    let progress = app_state.get_chapter_progress();
+    let scroll_position = app_state.scroll_position;
     let size = frame.area();
 
-    // Create the layout sections
+    // Create the layout sections; hidden bars leave their rows to the content
+    let bar_height = |shown: bool| if shown { 3 } else { 0 };
     let chunks = Layout::default()
       .direction(Direction::Vertical)
       .constraints([
-        Constraint::Length(3), // Header
-        Constraint::Min(0),    // Content
-        Constraint::Length(3), // Footer
+        Constraint::Length(bar_height(app_state.show_header)), // Header
+        Constraint::Min(0),                                    // Content
+        Constraint::Length(bar_height(app_state.show_footer)), // Footer
       ])
       .split(size);
 
     // Header with title
-    let title_block = Block::default().borders(Borders::ALL).title(title);
+    if app_state.show_header {
+      let title_block = Block::default().borders(Borders::ALL).title(title.as_str());
 
-    let title_paragraph = Paragraph::new("").block(title_block);
+      let title_paragraph = Paragraph::new("").block(title_block);
 
-    frame.render_widget(title_paragraph, chunks[0]);
+      frame.render_widget(title_paragraph, chunks[0]);
+    }
 
     // Content area
     let content_block = Block::default().borders(Borders::NONE);
 
-    // Build the content with proper formatting using Lines and Spans
-    let mut content_lines: Vec<Line> = Vec::new();
-
-    for block in &chapter.blocks {
-      match block {
-        RenderableBlock::Paragraph(text) => {
-          // Add an empty line before paragraph for spacing
-          content_lines.push(Line::from(""));
+    // Line numbers get a column of their own, wide enough for the last line
+    // of the chapter as laid out last time
+    let gutter_width = if app_state.show_line_numbers {
+      let digits = app_state
+        .chapter_layout
+        .total_lines
+        .max(999)
+        .to_string()
+        .len();
+      (digits + 1) as u16
+    } else {
+      0
+    };
+    // The outline sidebar only opens where it leaves room to read
+    let (outline_area, text_area) =
+      if app_state.show_outline && chunks[1].width >= OUTLINE_MIN_SCREEN_WIDTH {
+        let width = (chunks[1].width / 4).min(OUTLINE_MAX_WIDTH);
+        let [outline, text] =
+          Layout::horizontal([Constraint::Length(width), Constraint::Min(0)]).areas(chunks[1]);
+        (Some(outline), text)
+      } else {
+        (None, chunks[1])
+      };
+    if let Some(area) = outline_area {
+      render_outline(frame, app_state, area);
+    }
 
-          // For paragraphs, we'll wrap the text and add it as multiple lines
-          let wrapped_lines = wrap_text(text, size.width as usize - 2); // -2 for borders/padding
-          for line in wrapped_lines {
-            content_lines.push(Line::from(line));
-          }
+    let [gutter_area, mut content_area] =
+      Layout::horizontal([Constraint::Length(gutter_width), Constraint::Min(0)]).areas(text_area);
+    // Past the configured measure the screen is left blank
+    if app_state.config.max_width > 0 {
+      let max_width = (app_state.config.max_width + 2).min(u16::MAX as usize) as u16;
+      content_area.width = content_area.width.min(max_width);
+    }
 
-          // Add an empty line after paragraph for spacing
-          content_lines.push(Line::from(""));
-        }
-        RenderableBlock::Heading(level, text) => {
-          // Add an empty line before heading for spacing
-          content_lines.push(Line::from(""));
-
-          // For headings, we'll add the text with appropriate styling
-          let (heading_prefix, heading_suffix, style) = match level {
-            1 => (
-              "=".repeat(std::cmp::min(5, size.width as usize / 4)),
-              "=".repeat(std::cmp::min(5, size.width as usize / 4)),
-              Style::default().add_modifier(Modifier::BOLD),
-            ),
-            2 => (
-              "-".repeat(std::cmp::min(3, size.width as usize / 6)),
-              "-".repeat(std::cmp::min(3, size.width as usize / 6)),
-              Style::default().add_modifier(Modifier::BOLD),
-            ),
-            3 => (
-              "###".to_string(),
-              "".to_string(),
-              Style::default().add_modifier(Modifier::BOLD),
-            ),
-            4 => (
-              "####".to_string(),
-              "".to_string(),
-              Style::default().add_modifier(Modifier::UNDERLINED),
-            ),
-            5 => (
-              "#####".to_string(),
-              "".to_string(),
-              Style::default().add_modifier(Modifier::UNDERLINED),
-            ),
-            _ => ("######".to_string(), "".to_string(), Style::default()),
-          };
-
-          let heading_line = Line::from(vec![
-            Span::raw(" "),
-            Span::styled(heading_prefix.clone(), style),
-            Span::raw(" "),
-            Span::styled(text.clone(), style),
-            Span::raw(" "),
-            Span::styled(heading_suffix.clone(), style),
-            Span::raw(" "),
-          ]);
-
-          content_lines.push(heading_line);
-
-          // Add an empty line after heading for spacing
-          content_lines.push(Line::from(""));
+    // All wrapping happens here rather than in the Paragraph widget, so the
+    // line offsets recorded in the layout match what is on screen. Two
+    // columns are kept free on the right for margin markers.
+    let wrap_width = (content_area.width as usize).saturating_sub(2);
+
+    // Wrapping the whole chapter is only redone when something it depends on
+    // changes; scrolling just picks out the visible lines
+    let key = LayoutKey {
+      content_version: app_state.content_version(),
+      wrap_width,
+      screen_width: size.width,
+      viewport_height: content_area.height as usize,
+    };
+    let new_layout = (app_state.chapter_layout.key.as_ref() != Some(&key)).then(|| {
+      let mut layout = lay_out_chapter(app_state, wrap_width, size.width);
+      layout.viewport_height = key.viewport_height;
+      layout.key = Some(key);
+      layout
+    });
+    let layout = new_layout.as_ref().unwrap_or(&app_state.chapter_layout);
+
+    let visible = scroll_position.min(layout.total_lines)
+      ..(scroll_position + content_area.height as usize).min(layout.total_lines);
+    let mut content_lines = layout.lines[visible.clone()].to_vec();
+
+    // Apply persisted and in-progress highlights
+    for (index, line) in visible.clone().zip(content_lines.iter_mut()) {
+      if app_state.is_line_highlighted(index) {
+        for span in line.spans.iter_mut() {
+          span.style = span.style.patch(app_state.palette.highlight);
         }
-        RenderableBlock::Image(path) => {
-          // Add an empty line before image for spacing
-          content_lines.push(Line::from(""));
-
-          // Add image info with special styling
-          content_lines.push(Line::from(vec![
-            Span::raw("[Image: "),
-            Span::styled(
-              path.clone(),
-              Style::default().add_modifier(Modifier::ITALIC),
-            ),
-            Span::raw("]"),
-          ]));
-          content_lines.push(Line::from(
-            "(Press 'i' when this line is visible to view the image)",
-          ));
-
-          // Add an empty line after image for spacing
-          content_lines.push(Line::from(""));
+      }
+    }
+
+    // Mark every match of the active search
+    if let Some(search) = &app_state.search {
+      for (line, text) in content_lines
+        .iter_mut()
+        .zip(&layout.line_texts[visible.clone()])
+      {
+        for (start, end) in search.find_all(text) {
+          style_char_range(line, start, end, app_state.palette.search_match);
         }
-        RenderableBlock::ImagePlaceholder(description) => {
-          // Add an empty line before image for spacing
-          content_lines.push(Line::from(""));
-
-          // Add image placeholder info
-          content_lines.push(Line::from(vec![
-            Span::raw("[Image: "),
-            Span::styled(
-              description.clone(),
-              Style::default().add_modifier(Modifier::ITALIC),
-            ),
-            Span::raw("]"),
-          ]));
-
-          // Add an empty line after image for spacing
-          content_lines.push(Line::from(""));
+      }
+    }
+
+    // Show the link selected for following
+    if let Some(link) = app_state
+      .selected_link
+      .and_then(|index| layout.links.get(index))
+      .filter(|link| visible.contains(&link.line))
+    {
+      style_char_range(
+        &mut content_lines[link.line - visible.start],
+        link.start,
+        link.end,
+        Style::default().add_modifier(Modifier::REVERSED),
+      );
+    }
+
+    // Show the word cursor used for dictionary lookups
+    if let Some((line, word)) = app_state.word_cursor
+      && visible.contains(&line)
+      && let Some((start, end)) = layout
+        .line_texts
+        .get(line)
+        .and_then(|text| word_char_ranges(text).get(word).copied())
+    {
+      style_char_range(
+        &mut content_lines[line - visible.start],
+        start,
+        end,
+        Style::default().add_modifier(Modifier::REVERSED),
+      );
+    }
+
+    // Code blocks scroll sideways together; the rest of the text stays put
+    if app_state.code_scroll > 0 {
+      for (index, line) in visible.clone().zip(content_lines.iter_mut()) {
+        if layout
+          .code_lines
+          .binary_search_by_key(&index, |&(line, _)| line)
+          .is_ok()
+        {
+          drop_columns(line, app_state.code_scroll);
         }
       }
     }
 
     // Create the content paragraph with scrolling
+    let alignment = if app_state.is_rtl() {
+      Alignment::Right
+    } else {
+      Alignment::Left
+    };
     let content_paragraph = Paragraph::new(content_lines)
       .block(content_block)
-      .wrap(Wrap { trim: false })
-      .scroll((scroll_position as u16, 0));
+      .alignment(alignment);
+
+    frame.render_widget(content_paragraph, content_area);
+
+    if gutter_width > 0 {
+      let numbers: Vec<Line> = visible
+        .clone()
+        .map(|line| {
+          Line::styled(
+            format!("{:>width$} ", line + 1, width = gutter_width as usize - 1),
+            Style::default().fg(app_state.palette.line_number),
+          )
+        })
+        .collect();
+      frame.render_widget(Paragraph::new(numbers), gutter_area);
+    }
 
-    frame.render_widget(content_paragraph, chunks[1]);
+    // Mark annotated positions in the right margin
+    for line in app_state.annotated_lines() {
+      if line >= scroll_position && line < scroll_position + content_area.height as usize {
+        let y = content_area.y + (line - scroll_position) as u16;
+        let x = content_area.right().saturating_sub(1);
+        frame.buffer_mut().set_string(
+          x,
+          y,
+          "✎",
+          Style::default().fg(app_state.palette.annotation_marker),
+        );
+      }
+    }
 
-    // Footer with progress
-    let progress_text = format!(
-      "Progress: {:.1}% | Scroll: {}",
-      progress * 100.0,
-      scroll_position
-    );
-    let footer_block = Block::default().borders(Borders::ALL).title(progress_text);
+    // Footer with progress, plus the active prompt or latest status message
+    let footer_text = match (&app_state.prompt, &app_state.status_message) {
+      (Some(prompt), _) => Some(prompt_line(prompt)),
+      (None, Some(message)) => Some(Line::from(message.as_str())),
+      (None, None) => None,
+    };
+    if app_state.show_footer {
+      let footer_block = Block::default()
+        .borders(Borders::ALL)
+        .title(footer_title(app_state, progress));
+
+      let footer_paragraph = Paragraph::new(footer_text.unwrap_or_default()).block(footer_block);
+
+      frame.render_widget(footer_paragraph, chunks[2]);
+    } else if let Some(text) = footer_text
+      && chunks[1].height > 0
+    {
+      // Without a footer the prompt or message covers the last line of text,
+      // so the text doesn't move when it comes and goes
+      let area = Rect {
+        y: chunks[1].bottom() - 1,
+        height: 1,
+        ..chunks[1]
+      };
+      frame.render_widget(Clear, area);
+      frame.render_widget(Paragraph::new(text), area);
+    }
 
-    let footer_paragraph = Paragraph::new("").block(footer_block);
+    if let Some(overlay) = &app_state.overlay {
+      let overlay_title = match &overlay.kind {
+        OverlayKind::Toc => "Contents (Enter: jump)".to_string(),
+        OverlayKind::Bookmarks => "Bookmarks (Enter: jump, d: delete)".to_string(),
+        OverlayKind::Annotations => "Annotations (Enter: jump, d: delete)".to_string(),
+        OverlayKind::Highlights => "Highlights (Enter: jump, d: delete)".to_string(),
+        OverlayKind::Diagnostics => format!(
+          "Resources that failed to load ({})",
+          app_state.resource_errors.len()
+        ),
+        OverlayKind::Footnotes => "Notes in this chapter".to_string(),
+        OverlayKind::Lookup(word, _) => format!("Definition: {}", word),
+        OverlayKind::Stats => "This session".to_string(),
+        OverlayKind::Outline => "Outline (Enter: jump)".to_string(),
+        OverlayKind::ImageUnsupported(_) => "Image not shown".to_string(),
+        OverlayKind::ChapterFinder(query) => format!("Go to chapter: {}_", query),
+      };
+      render_list_popup(
+        frame,
+        &overlay_title,
+        app_state.overlay_entries(),
+        overlay.selected,
+      );
+    }
 
-    frame.render_widget(footer_paragraph, chunks[2]);
+    new_layout
   }
 
   pub fn render_image(
     frame: &mut ratatui::Frame,
-    image_path: &str,
+    view: &mut ImageView,
     title: &str,
     progress: f64,
     scroll_position: usize,
@@ -193,18 +347,15 @@ impl Renderer {
 
     frame.render_widget(title_paragraph, chunks[0]);
 
-    let picker = Picker::from_fontsize((8, 12));
-
-    // Load an image with the image crate.
-    let dyn_img = image::ImageReader::open(image_path)
-      .unwrap()
-      .decode()
-      .unwrap();
-
-    // Create the Protocol which will be used by the widget.
-    let mut image = picker.new_resize_protocol(dyn_img);
-
-    frame.render_stateful_widget(StatefulImage::default(), chunks[1], &mut image);
+    match &mut view.image {
+      Ok(image) => {
+        frame.render_stateful_widget(StatefulImage::default(), chunks[1], image);
+      }
+      Err(e) => {
+        let message = format!("Failed to load image {}: {}", view.path.display(), e);
+        frame.render_widget(Paragraph::new(message), chunks[1]);
+      }
+    }
 
     // Footer with progress
     let progress_text = format!(
@@ -220,59 +371,390 @@ impl Renderer {
   }
 }
 
-// Helper function to wrap text to fit within a specified width
-fn wrap_text(text: &str, width: usize) -> Vec<String> {
-  let mut lines = Vec::new();
-  let mut current_line = String::new();
+// The prompt's label and text, with the character under the cursor reversed
+fn prompt_line(prompt: &Prompt) -> Line<'_> {
+  let (before, after) = prompt.input.split();
+  let mut rest = after.chars();
+  let under_cursor = rest.next().map_or(" ".to_string(), String::from);
+  Line::from(vec![
+    Span::raw(format!("{}: {}", prompt.kind.label(), before)),
+    Span::styled(
+      under_cursor,
+      Style::default().add_modifier(Modifier::REVERSED),
+    ),
+    Span::raw(rest.as_str()),
+  ])
+}
 
-  for word in text.split_whitespace() {
-    // Check if adding this word would exceed the width
-    let test_line = if current_line.is_empty() {
-      word.to_string()
-    } else {
-      format!("{} {}", current_line, word)
+// Wrap every block of the chapter into content lines, recording where blocks,
+// images and links ended up
+fn lay_out_chapter(app_state: &AppState, wrap_width: usize, screen_width: u16) -> ChapterLayout {
+  let chapter = &app_state.renderable_chapter;
+  let mut layout = ChapterLayout::default();
+  let mut content_lines: Vec<Line<'static>> = Vec::new();
+
+  // A debug view replaces the formatted chapter with plain text
+  for line in &app_state.debug_text {
+    let wrapped = wrap_runs(&[TextRun::plain(line.clone())], wrap_width, 0);
+    content_lines.extend(
+      wrapped
+        .into_iter()
+        .map(|line| terminal_line(line, &app_state.palette)),
+    );
+  }
+
+  if app_state.debug_view.is_none() {
+    let config = &app_state.config;
+    let options = LayoutOptions {
+      paragraph_indent: config.paragraph_indent,
+      collapse_blank_lines: config.collapse_blank_lines,
+      drop_caps: config.drop_caps,
+      scene_break: config.scene_break.clone(),
+      image_placeholder: config.image_placeholder.clone(),
+      // Pointing at `i` only helps when it can show something
+      image_hint: config.image_hint && app_state.can_show_images(),
+      image_sizes: app_state.image_sizes(),
+      show_images: app_state.show_images,
+      wrap_code: false,
+      screen_width: screen_width as usize,
     };
+    for line in chapter.lines(wrap_width, &options) {
+      // Blocks that came out empty start where the next one does
+      while layout.block_offsets.len() <= line.block {
+        layout.block_offsets.push(content_lines.len());
+      }
+      // Remember where images sit so `i` can pick the visible one
+      if line.image {
+        layout.image_offsets.push(content_lines.len());
+      }
+      record_links(&line, content_lines.len(), &mut layout.links);
+      if line.code {
+        let width: usize = line.runs.iter().map(|run| run.text.width()).sum();
+        let overflow = (line.indent + width).saturating_sub(wrap_width);
+        layout.code_lines.push((content_lines.len(), overflow));
+      }
+      content_lines.push(terminal_line(line, &app_state.palette));
+    }
+    layout
+      .block_offsets
+      .resize(chapter.blocks.len(), content_lines.len());
+
+    // Hidden images still have a place in the text, so `i` can pick one
+    if !app_state.show_images {
+      layout.image_offsets = chapter
+        .blocks
+        .iter()
+        .zip(&layout.block_offsets)
+        .filter(|(block, _)| matches!(block, RenderableBlock::Image { .. }))
+        .map(|(_, &offset)| offset)
+        .collect();
+    }
+  }
 
-    if test_line.len() <= width {
-      current_line = test_line;
-    } else {
-      // If the current line is not empty, add it to lines
-      if !current_line.is_empty() {
-        lines.push(current_line);
-        current_line = word.to_string();
+  layout.total_lines = content_lines.len();
+  layout.line_texts = content_lines
+    .iter()
+    .map(|line| {
+      line
+        .spans
+        .iter()
+        .map(|span| span.content.as_ref())
+        .collect()
+    })
+    .collect();
+  layout.lines = content_lines;
+  layout
+}
+
+// Draw the chapter's headings down the left of the screen, marking the one
+// being read
+fn render_outline(frame: &mut ratatui::Frame, app_state: &AppState, area: Rect) {
+  let current = app_state.current_heading();
+  let width = area.width.saturating_sub(1) as usize;
+  let lines: Vec<Line> = app_state
+    .outline()
+    .into_iter()
+    .enumerate()
+    .map(|(index, (level, title, _))| {
+      let entry = format!("{}{}", " ".repeat(level - 1), title);
+      let entry: String = entry.chars().take(width).collect();
+      if current == Some(index) {
+        Line::styled(entry, Style::default().add_modifier(Modifier::REVERSED))
       } else {
-        // If the word itself is longer than width, we need to split it
-        if word.len() > width {
-          // Add as much as we can to the current line
-          let (first_part, rest) = word.split_at(width);
-          lines.push(first_part.to_string());
-
-          // Handle the rest of the word
-          let mut remaining = rest;
-          while remaining.len() > width {
-            let (part, rest) = remaining.split_at(width);
-            lines.push(part.to_string());
-            remaining = rest;
-          }
-          if !remaining.is_empty() {
-            current_line = remaining.to_string();
-          }
-        } else {
-          current_line = word.to_string();
-        }
+        Line::raw(entry)
+      }
+    })
+    .collect();
+
+  // Keep the current heading on screen in long outlines
+  let skip = current.map_or(0, |index| (index + 1).saturating_sub(area.height as usize));
+  let block = Block::default().borders(Borders::RIGHT);
+  frame.render_widget(
+    Paragraph::new(lines).block(block).scroll((skip as u16, 0)),
+    area,
+  );
+}
+
+// Draw a centered popup with a selectable list of entries
+fn render_list_popup(
+  frame: &mut ratatui::Frame,
+  title: &str,
+  entries: Vec<String>,
+  selected: usize,
+) {
+  let area = centered_rect(frame.area(), 70, 60);
+  let block = Block::default().borders(Borders::ALL).title(title);
+
+  frame.render_widget(Clear, area);
+
+  if entries.is_empty() {
+    frame.render_widget(Paragraph::new("(empty)").block(block), area);
+    return;
+  }
+
+  let items: Vec<ListItem> = entries.into_iter().map(ListItem::new).collect();
+  let list = List::new(items)
+    .block(block)
+    .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+  let mut state = ListState::default().with_selected(Some(selected));
+
+  frame.render_stateful_widget(list, area, &mut state);
+}
+
+// Helper function to fill in the configured header template
+fn header_text(app_state: &AppState) -> String {
+  let handler = &app_state.epub_handler;
+  let fields = [
+    ("{book}", handler.book_title().unwrap_or_default()),
+    ("{author}", handler.book_author().unwrap_or_default()),
+    ("{language}", handler.book_language().unwrap_or_default()),
+    ("{chapter}", app_state.get_chapter_title()),
+    (
+      "{number}",
+      (app_state.current_chapter_index + 1).to_string(),
+    ),
+    ("{total}", handler.get_chapter_count().to_string()),
+  ];
+
+  fill_template(&app_state.config.header_format, &fields)
+}
+
+// Helper function to fill in the configured footer template
+fn footer_title(app_state: &AppState, progress: f64) -> String {
+  let time_left = match app_state.minutes_left() {
+    Some(minutes) if minutes >= 60.0 => format!(
+      "{} h {} min left",
+      (minutes / 60.0) as u64,
+      (minutes % 60.0) as u64
+    ),
+    Some(minutes) => format!("{} min left", minutes.ceil() as u64),
+    None => "? min left".to_string(),
+  };
+  let fields = [
+    ("{percent}", format!("{:.1}", progress * 100.0)),
+    (
+      "{chapter_percent}",
+      format!("{:.1}", app_state.chapter_fraction() * 100.0),
+    ),
+    (
+      "{number}",
+      (app_state.current_chapter_index + 1).to_string(),
+    ),
+    (
+      "{total}",
+      app_state.epub_handler.get_chapter_count().to_string(),
+    ),
+    ("{scroll}", app_state.scroll_position.to_string()),
+    ("{time_left}", time_left),
+    ("{clock}", chrono::Local::now().format("%H:%M").to_string()),
+  ];
+
+  fill_template(&app_state.config.footer_format, &fields)
+}
+
+// Helper function to replace each `{field}` of a template with its value,
+// in one pass so values containing braces are never expanded themselves
+fn fill_template(template: &str, fields: &[(&str, String)]) -> String {
+  let mut text = String::with_capacity(template.len());
+  let mut rest = template;
+
+  while let Some(start) = rest.find('{') {
+    text.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let value = rest.find('}').and_then(|end| {
+      let field = &rest[..=end];
+      fields
+        .iter()
+        .find(|(name, _)| *name == field)
+        .map(|(_, value)| (field.len(), value))
+    });
+    match value {
+      Some((len, value)) => {
+        text.push_str(value);
+        rest = &rest[len..];
+      }
+      None => {
+        text.push('{');
+        rest = &rest[1..];
       }
     }
   }
+  text.push_str(rest);
+  text
+}
 
-  // Add the last line if it's not empty
-  if !current_line.is_empty() {
-    lines.push(current_line);
+// Helper function to find the character range of each word in a line
+fn word_char_ranges(text: &str) -> Vec<(usize, usize)> {
+  let mut ranges = Vec::new();
+  let mut word_start = None;
+
+  for (index, c) in text.chars().enumerate() {
+    match (c.is_whitespace(), word_start) {
+      (false, None) => word_start = Some(index),
+      (true, Some(start)) => {
+        ranges.push((start, index));
+        word_start = None;
+      }
+      _ => {}
+    }
   }
+  if let Some(start) = word_start {
+    ranges.push((start, text.chars().count()));
+  }
+
+  ranges
+}
+
+// Helper function to patch a style onto the characters `start..end` of a line,
+// splitting spans where the range begins and ends
+fn style_char_range(line: &mut Line<'static>, start: usize, end: usize, style: Style) {
+  let mut offset = 0;
+  let mut spans = Vec::new();
+
+  for span in std::mem::take(&mut line.spans) {
+    let chars: Vec<char> = span.content.chars().collect();
+    let span_start = start.clamp(offset, offset + chars.len()) - offset;
+    let span_end = end.clamp(offset, offset + chars.len()) - offset;
+    offset += chars.len();
+
+    if span_start >= span_end {
+      spans.push(span);
+      continue;
+    }
 
-  // If no lines were added (empty text), add an empty line
-  if lines.is_empty() {
-    lines.push(String::new());
+    let before: String = chars[..span_start].iter().collect();
+    let inside: String = chars[span_start..span_end].iter().collect();
+    let after: String = chars[span_end..].iter().collect();
+    if !before.is_empty() {
+      spans.push(Span::styled(before, span.style));
+    }
+    spans.push(Span::styled(inside, span.style.patch(style)));
+    if !after.is_empty() {
+      spans.push(Span::styled(after, span.style));
+    }
   }
 
-  lines
+  line.spans = spans;
+}
+
+// Helper function to carve a centered rectangle out of an area
+fn centered_rect(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+  let vertical = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([
+      Constraint::Percentage((100 - percent_y) / 2),
+      Constraint::Percentage(percent_y),
+      Constraint::Percentage((100 - percent_y) / 2),
+    ])
+    .split(area);
+
+  Layout::default()
+    .direction(Direction::Horizontal)
+    .constraints([
+      Constraint::Percentage((100 - percent_x) / 2),
+      Constraint::Percentage(percent_x),
+      Constraint::Percentage((100 - percent_x) / 2),
+    ])
+    .split(vertical[1])[1]
+}
+
+// Helper function to map inline text formatting onto a terminal style
+fn text_style(style: TextStyle, palette: &Palette) -> Style {
+  let mut result = Style::default();
+  if style.bold {
+    result = result.add_modifier(Modifier::BOLD);
+  }
+  if style.italic {
+    result = result.add_modifier(Modifier::ITALIC);
+  }
+  if style.underline || style.link.is_some() {
+    result = result.add_modifier(Modifier::UNDERLINED);
+  }
+  if style.link.is_some() {
+    result = result.fg(palette.link);
+  }
+  result
+}
+
+// Helper function to record where each link landed in content line `line_index`
+fn record_links(line: &RenderedLine, line_index: usize, links: &mut Vec<LinkSpan>) {
+  let mut column = line.indent;
+  for run in &line.runs {
+    let start = column;
+    column += run.text.chars().count();
+    let Some(link) = run.style.link else {
+      continue;
+    };
+    // A link continues across style changes inside it
+    match links.last_mut() {
+      Some(last) if last.line == line_index && last.link == link && last.end == start => {
+        last.end = column;
+      }
+      _ => links.push(LinkSpan {
+        line: line_index,
+        start,
+        end: column,
+        link,
+      }),
+    }
+  }
+}
+
+// Helper function to cut the first `columns` columns off a line
+fn drop_columns(line: &mut Line<'static>, columns: usize) {
+  let mut remaining = columns;
+  for span in &mut line.spans {
+    let mut cut = span.content.len();
+    for (at, c) in span.content.char_indices() {
+      if remaining == 0 {
+        cut = at;
+        break;
+      }
+      remaining = remaining.saturating_sub(c.width().unwrap_or(0));
+    }
+    span.content = span.content[cut..].to_string().into();
+    if remaining == 0 {
+      break;
+    }
+  }
+}
+
+// Helper function to turn a laid out line into a terminal line
+fn terminal_line(line: RenderedLine, palette: &Palette) -> Line<'static> {
+  let mut spans = Vec::new();
+  if line.indent > 0 {
+    spans.push(Span::raw(" ".repeat(line.indent)));
+  }
+  spans.extend(
+    line
+      .runs
+      .into_iter()
+      .map(|run| Span::styled(run.text, text_style(run.style, palette))),
+  );
+  let terminal_line = Line::from(spans);
+  if line.centered {
+    terminal_line.alignment(Alignment::Center)
+  } else {
+    terminal_line
+  }
 }
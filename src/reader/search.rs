@@ -0,0 +1,56 @@
+use regex::Regex;
+
+/// What the reader is searching the chapter for
+pub enum SearchPattern {
+  Plain(String), // Case-insensitive substring
+  Regex(Regex),
+}
+
+impl SearchPattern {
+  /// Build a pattern from search input, reporting invalid regular expressions
+  pub fn new(query: &str, regex: bool) -> Result<Self, String> {
+    if regex {
+      Regex::new(query)
+        .map(SearchPattern::Regex)
+        .map_err(|e| format!("Invalid regex: {}", e))
+    } else {
+      Ok(SearchPattern::Plain(query.chars().map(fold_case).collect()))
+    }
+  }
+
+  /// Character range of every match in a line of text
+  pub fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+    match self {
+      SearchPattern::Plain(query) => {
+        // Compare char by char so the ranges line up with the original text
+        let needle: Vec<char> = query.chars().collect();
+        let haystack: Vec<char> = text.chars().map(fold_case).collect();
+        let mut ranges = Vec::new();
+        let mut start = 0;
+
+        while !needle.is_empty() && start + needle.len() <= haystack.len() {
+          if haystack[start..start + needle.len()] == needle[..] {
+            ranges.push((start, start + needle.len()));
+            start += needle.len();
+          } else {
+            start += 1;
+          }
+        }
+
+        ranges
+      }
+      SearchPattern::Regex(regex) => regex
+        .find_iter(text)
+        .filter(|found| !found.is_empty())
+        .map(|found| {
+          let start = text[..found.start()].chars().count();
+          (start, start + found.as_str().chars().count())
+        })
+        .collect(),
+    }
+  }
+}
+
+fn fold_case(c: char) -> char {
+  c.to_lowercase().next().unwrap_or(c)
+}
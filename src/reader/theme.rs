@@ -0,0 +1,99 @@
+use ratatui::style::{Color, Modifier, Style};
+
+/// Whether the terminal draws dark text on a light background or the reverse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+  Dark,
+  Light,
+}
+
+impl Background {
+  /// Read an OSC 11 reply such as `ESC ] 11 ; rgb:ffff/ffff/dddd ESC \`
+  pub fn from_osc_reply(reply: &str) -> Option<Self> {
+    let rgb = reply.split("rgb:").nth(1)?;
+    let channels: Vec<f64> = rgb
+      .split('/')
+      .take(3)
+      .map(|channel| {
+        // Each channel has one to four hex digits
+        let digits: String = channel
+          .chars()
+          .take_while(char::is_ascii_hexdigit)
+          .collect();
+        let max = 16f64.powi(digits.len() as i32) - 1.0;
+        u32::from_str_radix(&digits, 16)
+          .ok()
+          .map(|value| value as f64 / max)
+      })
+      .collect::<Option<_>>()?;
+    let [red, green, blue] = channels[..] else {
+      return None;
+    };
+    Some(Self::from_luminance(
+      0.2126 * red + 0.7152 * green + 0.0722 * blue,
+    ))
+  }
+
+  /// Read the `COLORFGBG` variable some terminals set, such as `15;0`
+  pub fn from_colorfgbg(value: &str) -> Option<Self> {
+    let background: u8 = value.rsplit(';').next()?.parse().ok()?;
+    // Of the 16 basic colors, white and the bright ones other than gray are light
+    Some(if background == 7 || background > 8 {
+      Background::Light
+    } else {
+      Background::Dark
+    })
+  }
+
+  fn from_luminance(luminance: f64) -> Self {
+    if luminance > 0.5 {
+      Background::Light
+    } else {
+      Background::Dark
+    }
+  }
+}
+
+/// Colors the reader is drawn with
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+  pub link: Color,
+  pub highlight: Style,
+  pub search_match: Style,
+  pub line_number: Color,
+  pub annotation_marker: Color,
+}
+
+impl Palette {
+  pub const DARK: Palette = Palette {
+    link: Color::Cyan,
+    highlight: Style::new().bg(Color::Yellow).fg(Color::Black),
+    search_match: Style::new().bg(Color::LightBlue).fg(Color::Black),
+    line_number: Color::DarkGray,
+    annotation_marker: Color::Yellow,
+  };
+
+  pub const LIGHT: Palette = Palette {
+    link: Color::Blue,
+    highlight: Style::new().bg(Color::LightYellow).fg(Color::Black),
+    search_match: Style::new()
+      .bg(Color::LightCyan)
+      .fg(Color::Black)
+      .add_modifier(Modifier::BOLD),
+    line_number: Color::Gray,
+    annotation_marker: Color::Magenta,
+  };
+
+  pub fn for_background(background: Background) -> Self {
+    match background {
+      Background::Dark => Palette::DARK,
+      Background::Light => Palette::LIGHT,
+    }
+  }
+}
+
+impl Default for Palette {
+  fn default() -> Self {
+    Palette::DARK
+  }
+}
@@ -0,0 +1,40 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// Where `--verbose` timings go; the terminal belongs to the UI
+static LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Start appending timings to `$XDG_CACHE_HOME/creb/timing.log`
+pub fn enable() -> Result<(), String> {
+  let dir = dirs::cache_dir()
+    .ok_or_else(|| "No cache directory available".to_string())?
+    .join("creb");
+  std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+
+  let path = dir.join("timing.log");
+  let file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&path)
+    .map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+  let _ = LOG.set(Mutex::new(file));
+  Ok(())
+}
+
+/// Log how long something took since `started`, when timing is enabled
+pub fn record(started: Instant, label: impl FnOnce() -> String) {
+  let Some(log) = LOG.get() else {
+    return;
+  };
+  let elapsed = started.elapsed();
+  if let Ok(mut file) = log.lock() {
+    let _ = writeln!(
+      file,
+      "{:>10.3} ms  {}",
+      elapsed.as_secs_f64() * 1000.0,
+      label()
+    );
+  }
+}
@@ -1,5 +1,11 @@
-use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind};
+use crate::app::input::CursorMove;
+use crate::app::keymap::Keymap;
+use crate::reader::theme::Background;
+use ratatui::crossterm::event::{
+  DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEventKind, KeyModifiers,
+};
 use ratatui::{Terminal, backend::CrosstermBackend};
+use serde::Deserialize;
 use std::io;
 
 pub struct UI {
@@ -15,12 +21,15 @@ impl UI {
 
   pub fn init(&mut self) -> Result<(), Box<dyn std::error::Error>> {
     ratatui::crossterm::terminal::enable_raw_mode()?;
+    // Pasted text arrives in one piece rather than as key presses
+    ratatui::crossterm::execute!(io::stdout(), EnableBracketedPaste)?;
     let _ = self.clear_screen();
     Ok(())
   }
 
   pub fn restore(&mut self) -> Result<(), Box<dyn std::error::Error>> {
     let _ = self.clear_screen();
+    ratatui::crossterm::execute!(io::stdout(), DisableBracketedPaste)?;
     ratatui::crossterm::terminal::disable_raw_mode()?;
     Ok(())
   }
@@ -38,41 +47,207 @@ impl UI {
     Ok(())
   }
 
-  pub fn size(&self) -> ratatui::layout::Rect {
-    // Return a default size since we can't get the actual size without a mutable reference
-    ratatui::layout::Rect::new(0, 0, 80, 24)
+  /// Ask the terminal for its background color, falling back to the
+  /// `COLORFGBG` variable; `None` when neither tells
+  pub fn query_background(&mut self) -> Option<Background> {
+    query_osc_background().or_else(|| {
+      std::env::var("COLORFGBG")
+        .ok()
+        .and_then(|value| Background::from_colorfgbg(&value))
+    })
   }
 
-  pub fn handle_events(&self) -> Result<Option<UserAction>, Box<dyn std::error::Error>> {
+  pub fn handle_events(
+    &self,
+    mode: InputMode,
+    keymap: &Keymap,
+  ) -> Result<Option<UserAction>, Box<dyn std::error::Error>> {
     if ratatui::crossterm::event::poll(std::time::Duration::from_millis(100))? {
-      if let Event::Key(key) = ratatui::crossterm::event::read()? {
-        if key.kind == KeyEventKind::Press {
-          match key.code {
-            KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(UserAction::Quit)),
-            KeyCode::Char('j') | KeyCode::Down => {
-              return Ok(Some(UserAction::ScrollDown));
-            }
-            KeyCode::Char('k') | KeyCode::Up => return Ok(Some(UserAction::ScrollUp)),
-            KeyCode::Char(' ') => return Ok(Some(UserAction::PageDown)),
-            KeyCode::Char('b') => return Ok(Some(UserAction::PageUp)),
-            KeyCode::Char('l') | KeyCode::Right => {
-              return Ok(Some(UserAction::NextChapter));
-            }
-            KeyCode::Char('h') | KeyCode::Left => {
-              return Ok(Some(UserAction::PreviousChapter));
-            }
-            KeyCode::Char('i') => {
-              return Ok(Some(UserAction::ViewImage));
-            }
-            _ => {}
-          }
+      match ratatui::crossterm::event::read()? {
+        Event::Paste(text) if matches!(mode, InputMode::Prompt | InputMode::Finder) => {
+          return Ok(Some(UserAction::PromptPaste(text)));
         }
+        Event::Key(key) if key.kind == KeyEventKind::Press => {
+          return Ok(match mode {
+            InputMode::Reading => keymap
+              .get(key.code)
+              .filter(|_| key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT)
+              .or_else(|| Self::reading_action(key.code, key.modifiers)),
+            InputMode::Prompt => Self::prompt_action(key.code),
+            InputMode::Overlay => Self::overlay_action(key.code),
+            InputMode::Finder => Self::finder_action(key.code),
+            InputMode::ConfirmQuit => Self::confirm_quit_action(key.code),
+            // Any key closes the image
+            InputMode::Image => Some(UserAction::Cancel),
+          });
+        }
+        _ => {}
       }
     }
     Ok(None)
   }
+
+  fn reading_action(code: KeyCode, modifiers: KeyModifiers) -> Option<UserAction> {
+    match code {
+      KeyCode::Char('p') if modifiers.contains(KeyModifiers::CONTROL) => {
+        Some(UserAction::FindChapter)
+      }
+      KeyCode::Char('h') if modifiers.contains(KeyModifiers::CONTROL) => {
+        Some(UserAction::CycleDebugView)
+      }
+      KeyCode::Char('q') | KeyCode::Esc => Some(UserAction::Quit),
+      KeyCode::Char('j') | KeyCode::Down => Some(UserAction::ScrollDown),
+      KeyCode::Char('k') | KeyCode::Up => Some(UserAction::ScrollUp),
+      KeyCode::Char(' ') => Some(UserAction::PageDown),
+      KeyCode::Char('b') => Some(UserAction::PageUp),
+      KeyCode::Char('l') | KeyCode::Right => Some(UserAction::NextChapter),
+      KeyCode::Char('h') | KeyCode::Left => Some(UserAction::PreviousChapter),
+      KeyCode::Char('i') => Some(UserAction::ViewImage),
+      KeyCode::Char('I') => Some(UserAction::ToggleImages),
+      KeyCode::Tab => Some(UserAction::NextLink),
+      KeyCode::BackTab => Some(UserAction::PreviousLink),
+      KeyCode::Enter => Some(UserAction::FollowLink),
+      KeyCode::Char('P') => Some(UserAction::ShowPosition),
+      KeyCode::Char('r') => Some(UserAction::ReloadBook),
+      KeyCode::Char('#') => Some(UserAction::ToggleLineNumbers),
+      KeyCode::Char('B') => Some(UserAction::CycleBars),
+      KeyCode::Char('O') => Some(UserAction::ToggleOutline),
+      KeyCode::Char('o') => Some(UserAction::ShowOutline),
+      KeyCode::Char(']') => Some(UserAction::NextHeading),
+      KeyCode::Char('[') => Some(UserAction::PreviousHeading),
+      KeyCode::Char('t') => Some(UserAction::ShowToc),
+      KeyCode::Char('m') => Some(UserAction::AddBookmark),
+      KeyCode::Char('\'') => Some(UserAction::ShowBookmarks),
+      KeyCode::Char('a') => Some(UserAction::AddAnnotation),
+      KeyCode::Char('A') => Some(UserAction::ShowAnnotations),
+      KeyCode::Char('v') => Some(UserAction::ToggleHighlight),
+      KeyCode::Char('H') => Some(UserAction::ShowHighlights),
+      KeyCode::Char('w') => Some(UserAction::NextWord),
+      KeyCode::Char('W') => Some(UserAction::PreviousWord),
+      KeyCode::Char('D') => Some(UserAction::LookupWord),
+      KeyCode::Char('s') => Some(UserAction::Speak),
+      KeyCode::Char('S') => Some(UserAction::StopSpeaking),
+      KeyCode::Char('E') => Some(UserAction::ShowDiagnostics),
+      KeyCode::Char('F') => Some(UserAction::ShowFootnotes),
+      KeyCode::Char('T') => Some(UserAction::ShowStats),
+      KeyCode::Char(':') => Some(UserAction::StartCommand),
+      KeyCode::Char('/') => Some(UserAction::Search),
+      KeyCode::Char('n') => Some(UserAction::NextMatch),
+      KeyCode::Char('N') => Some(UserAction::PreviousMatch),
+      _ => None,
+    }
+  }
+
+  fn prompt_action(code: KeyCode) -> Option<UserAction> {
+    match code {
+      KeyCode::Char(c) => Some(UserAction::PromptInput(c)),
+      KeyCode::Backspace => Some(UserAction::PromptBackspace),
+      KeyCode::Left => Some(UserAction::PromptCursor(CursorMove::Left)),
+      KeyCode::Right => Some(UserAction::PromptCursor(CursorMove::Right)),
+      KeyCode::Home => Some(UserAction::PromptCursor(CursorMove::Home)),
+      KeyCode::End => Some(UserAction::PromptCursor(CursorMove::End)),
+      KeyCode::Tab => Some(UserAction::TogglePromptMode),
+      KeyCode::Enter => Some(UserAction::Confirm),
+      KeyCode::Esc => Some(UserAction::Cancel),
+      _ => None,
+    }
+  }
+
+  fn finder_action(code: KeyCode) -> Option<UserAction> {
+    match code {
+      KeyCode::Down => Some(UserAction::SelectNext),
+      KeyCode::Up => Some(UserAction::SelectPrevious),
+      KeyCode::Char(c) => Some(UserAction::PromptInput(c)),
+      KeyCode::Backspace => Some(UserAction::PromptBackspace),
+      KeyCode::Enter => Some(UserAction::Confirm),
+      KeyCode::Esc => Some(UserAction::Cancel),
+      _ => None,
+    }
+  }
+
+  // Any key other than `y` keeps reading
+  fn confirm_quit_action(code: KeyCode) -> Option<UserAction> {
+    match code {
+      KeyCode::Char('y') | KeyCode::Char('Y') => Some(UserAction::Confirm),
+      _ => Some(UserAction::Cancel),
+    }
+  }
+
+  fn overlay_action(code: KeyCode) -> Option<UserAction> {
+    match code {
+      KeyCode::Char('j') | KeyCode::Down => Some(UserAction::SelectNext),
+      KeyCode::Char('k') | KeyCode::Up => Some(UserAction::SelectPrevious),
+      KeyCode::Enter => Some(UserAction::Confirm),
+      KeyCode::Char('d') => Some(UserAction::DeleteSelected),
+      KeyCode::Char('q') | KeyCode::Esc => Some(UserAction::Cancel),
+      _ => None,
+    }
+  }
+}
+
+// Send the OSC 11 query and read the terminal's reply straight from stdin, so
+// that nothing is left buffered ahead of crossterm's event reader
+#[cfg(unix)]
+fn query_osc_background() -> Option<Background> {
+  use std::io::Write;
+
+  // SAFETY: only inspects the standard input descriptor
+  if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+    return None;
+  }
+  // Every terminal answers the device attributes query that follows, so the
+  // reply ends even when the background color isn't reported
+  let mut stdout = io::stdout();
+  stdout.write_all(b"\x1b]11;?\x1b\\\x1b[c").ok()?;
+  stdout.flush().ok()?;
+
+  let mut reply = Vec::new();
+  loop {
+    let mut stdin = libc::pollfd {
+      fd: libc::STDIN_FILENO,
+      events: libc::POLLIN,
+      revents: 0,
+    };
+    let mut byte = 0u8;
+    // SAFETY: polls one valid descriptor and reads a byte into a local
+    let read = unsafe {
+      if libc::poll(&mut stdin, 1, 200) <= 0 {
+        break;
+      }
+      libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1)
+    };
+    if read != 1 {
+      break;
+    }
+    reply.push(byte);
+    // The device attributes reply, `ESC [ ? ... c`, comes last
+    if byte == b'c' && reply.windows(3).any(|window| window == b"\x1b[?") {
+      break;
+    }
+  }
+  Background::from_osc_reply(&String::from_utf8_lossy(&reply))
+}
+
+#[cfg(not(unix))]
+fn query_osc_background() -> Option<Background> {
+  None
+}
+
+/// How key presses are interpreted, depending on what is on screen
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+  Reading,
+  Prompt,
+  Overlay,
+  Finder, // Overlay filtered by typing
+  ConfirmQuit,
+  Image, // An image shown over the whole screen
 }
 
+/// Something the user asked for; reading actions can be bound to keys in the
+/// config file by their kebab-case names
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum UserAction {
   Quit,
   NextChapter,
@@ -82,4 +257,58 @@ pub enum UserAction {
   PageDown,
   PageUp,
   ViewImage,
+  ToggleImages,
+  NextLink,
+  PreviousLink,
+  FollowLink,
+  ShowPosition,
+  ReloadBook,
+  ToggleLineNumbers,
+  CycleBars,
+  ToggleOutline,
+  ShowOutline,
+  NextHeading,
+  PreviousHeading,
+  CycleDebugView,
+  ShowToc,
+  AddBookmark,
+  ShowBookmarks,
+  AddAnnotation,
+  ShowAnnotations,
+  ToggleHighlight,
+  ShowHighlights,
+  NextWord,
+  PreviousWord,
+  LookupWord,
+  Speak,
+  StopSpeaking,
+  ShowDiagnostics,
+  ShowFootnotes,
+  ShowStats,
+  FindChapter,
+  StartCommand,
+  Search,
+  NextMatch,
+  PreviousMatch,
+  // The rest only come from prompts and overlays, so they can't be bound
+  #[serde(skip)]
+  PromptInput(char),
+  #[serde(skip)]
+  PromptBackspace,
+  #[serde(skip)]
+  PromptCursor(CursorMove),
+  #[serde(skip)]
+  PromptPaste(String),
+  #[serde(skip)]
+  TogglePromptMode,
+  #[serde(skip)]
+  SelectNext,
+  #[serde(skip)]
+  SelectPrevious,
+  #[serde(skip)]
+  DeleteSelected,
+  #[serde(skip)]
+  Confirm,
+  #[serde(skip)]
+  Cancel,
 }
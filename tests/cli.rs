@@ -0,0 +1,129 @@
+use std::process::Command;
+
+#[test]
+fn missing_file_fails_before_starting_the_ui() {
+  let dir = std::env::temp_dir();
+  let output = Command::new(env!("CARGO_BIN_EXE_creb"))
+    .arg(dir.join("creb-no-such-book.epub"))
+    .arg("--config")
+    .arg(dir.join("creb-no-such-config.toml"))
+    .output()
+    .expect("failed to run creb");
+
+  assert_eq!(output.status.code(), Some(1));
+  assert!(output.stdout.is_empty());
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.starts_with("creb: No such file"), "{}", stderr);
+}
+
+#[test]
+fn malformed_position_is_rejected() {
+  let output = Command::new(env!("CARGO_BIN_EXE_creb"))
+    .arg("book.epub")
+    .arg("--position")
+    .arg("chapter.xhtml")
+    .output()
+    .expect("failed to run creb");
+
+  assert!(!output.status.success());
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("expected path@offset"), "{}", stderr);
+}
+
+#[test]
+fn export_width_has_a_minimum() {
+  let output = Command::new(env!("CARGO_BIN_EXE_creb"))
+    .arg("book.epub")
+    .arg("--export-text")
+    .arg("--width")
+    .arg("5")
+    .output()
+    .expect("failed to run creb");
+
+  assert!(!output.status.success());
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("too narrow"), "{}", stderr);
+}
+
+#[test]
+fn exported_text_fits_the_width() {
+  let book = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/prose.epub");
+  let output = Command::new(env!("CARGO_BIN_EXE_creb"))
+    .arg(book)
+    .arg("--export-text")
+    .arg("--width")
+    .arg("40")
+    .output()
+    .expect("failed to run creb");
+
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  let text = String::from_utf8(output.stdout).expect("exported text isn't UTF-8");
+  assert!(text.contains("remaining_miles"), "{}", text);
+  for line in text.lines() {
+    assert!(
+      line.chars().count() <= 40,
+      "{:?} is wider than 40 columns",
+      line
+    );
+  }
+}
+
+#[test]
+fn only_reading_actions_can_be_bound_to_keys() {
+  let config = std::env::temp_dir().join(format!("creb-keys-{}.toml", std::process::id()));
+  std::fs::write(&config, "[keys]\nx = \"confirm\"\n").expect("failed to write config");
+  let output = Command::new(env!("CARGO_BIN_EXE_creb"))
+    .arg("book.epub")
+    .arg("--config")
+    .arg(&config)
+    .output()
+    .expect("failed to run creb");
+  std::fs::remove_file(&config).ok();
+
+  assert_eq!(output.status.code(), Some(1));
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("unknown variant `confirm`"), "{}", stderr);
+}
+
+#[test]
+fn books_without_chapters_fail_before_starting_the_ui() {
+  let dir = std::env::temp_dir();
+  let book = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/empty.epub");
+  let output = Command::new(env!("CARGO_BIN_EXE_creb"))
+    .arg(book)
+    .arg("--config")
+    .arg(dir.join("creb-no-such-config.toml"))
+    .output()
+    .expect("failed to run creb");
+
+  assert_eq!(output.status.code(), Some(1));
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("no readable chapters"), "{}", stderr);
+}
+
+#[test]
+fn single_chapter_books_start_at_their_only_chapter() {
+  let dir = std::env::temp_dir();
+  let book = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/prose.epub");
+  let output = Command::new(env!("CARGO_BIN_EXE_creb"))
+    .arg(book)
+    .arg("--config")
+    .arg(dir.join("creb-no-such-config.toml"))
+    .arg("--chapter")
+    .arg("0")
+    .arg("--print-position")
+    .output()
+    .expect("failed to run creb");
+
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  assert_eq!(stdout.trim(), "OEBPS/text.xhtml@0");
+}
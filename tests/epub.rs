@@ -0,0 +1,265 @@
+use creb::epub::handler::temp_file_name;
+use creb::{EpubHandler, LayoutOptions, RenderableBlock, TextRun, runs_text};
+use std::path::Path;
+
+fn open(fixture: &str) -> EpubHandler {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/fixtures")
+    .join(fixture);
+  EpubHandler::open(path).expect("failed to open fixture")
+}
+
+#[test]
+fn minimal_book_reads_chapter_by_chapter() {
+  let mut book = open("minimal");
+  assert_eq!(book.get_chapter_count(), 2);
+  assert_eq!(book.book_author().as_deref(), Some("Fixture Author"));
+
+  let titles: Vec<_> = book
+    .toc_entries()
+    .iter()
+    .map(|entry| (entry.title.as_str(), entry.chapter))
+    .collect();
+  assert_eq!(titles, [("Chapter One", 0), ("Chapter Two", 1)]);
+
+  let chapter = book.chapter(1).expect("failed to read chapter");
+  assert_eq!(
+    chapter.blocks,
+    [
+      RenderableBlock::Heading(1, vec![TextRun::plain("Chapter Two")]),
+      RenderableBlock::Paragraph(vec![TextRun::plain("The afternoon was quieter.")]),
+    ]
+  );
+}
+
+#[test]
+fn images_resolve_to_their_resource_and_lay_out_as_placeholders() {
+  let mut book = open("images.epub");
+  let chapter = book.chapter(0).expect("failed to read chapter");
+
+  let Some(RenderableBlock::Image { src, alt, .. }) = chapter
+    .blocks
+    .iter()
+    .find(|block| matches!(block, RenderableBlock::Image { .. }))
+  else {
+    panic!("expected an image in {:?}", chapter.blocks);
+  };
+  assert_eq!(alt.as_deref(), Some("A red dot"));
+
+  let extracted = book.extract_resource(src).expect("failed to extract image");
+  let data = std::fs::read(extracted).expect("failed to read extracted image");
+  assert!(data.starts_with(b"\x89PNG"));
+
+  let lines: Vec<_> = chapter.lines(40, &LayoutOptions::default()).collect();
+  let image_lines: Vec<String> = lines
+    .iter()
+    .filter(|line| line.image)
+    .map(|line| runs_text(&line.runs))
+    .collect();
+  assert_eq!(image_lines, ["[Image: A red dot]"]);
+}
+
+#[test]
+fn nav_document_is_recognised_and_skipped_at_the_start() {
+  let book = open("nav.epub");
+  assert_eq!(book.get_chapter_count(), 3);
+  assert!(book.is_navigation(1));
+  assert!(!book.is_navigation(2));
+  assert_eq!(book.first_content_chapter(), 2);
+}
+
+#[test]
+fn malformed_chapters_fall_back_to_one_paragraph() {
+  let mut book = open("malformed.epub");
+  let chapter = book.chapter(0).expect("failed to read chapter");
+
+  let [RenderableBlock::Paragraph(runs)] = chapter.blocks.as_slice() else {
+    panic!("expected a single paragraph, got {:?}", chapter.blocks);
+  };
+  assert!(runs_text(runs).contains("And more"));
+}
+
+#[test]
+fn toc_sections_of_one_file_share_its_chapter() {
+  let mut book = open("sections.epub");
+  assert_eq!(book.get_chapter_count(), 1);
+
+  let entries = book.toc_entries().to_vec();
+  let targets: Vec<_> = entries
+    .iter()
+    .map(|entry| (entry.chapter, entry.fragment.as_deref()))
+    .collect();
+  assert_eq!(
+    targets,
+    [(0, Some("one")), (0, Some("two")), (0, Some("three"))]
+  );
+  assert_eq!(
+    book.toc_anchor(&entries[1]).as_deref(),
+    Some("OEBPS/text.xhtml#two")
+  );
+
+  let anchors = book.chapter(0).expect("failed to read chapter").anchors;
+  assert_eq!(anchors["one"], 0);
+  assert_eq!(anchors["two"], 2);
+  assert_eq!(anchors["three"], 4);
+}
+
+#[test]
+fn books_open_whatever_their_file_is_called() {
+  let book = open("minimal");
+  assert_eq!(book.book_title().as_deref(), Some("Minimal"));
+}
+
+#[test]
+fn files_that_are_not_zip_archives_are_rejected() {
+  let error = match EpubHandler::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")) {
+    Ok(_) => panic!("opened a manifest as a book"),
+    Err(error) => error,
+  };
+  assert!(error.starts_with("Not an EPUB"), "{}", error);
+}
+
+#[test]
+fn page_progression_direction_is_read_from_the_spine() {
+  assert!(open("rtl.epub").is_page_progression_rtl());
+  assert!(!open("minimal").is_page_progression_rtl());
+}
+
+#[test]
+fn right_to_left_books_turn_forward_to_the_left() {
+  let rtl = open("rtl.epub");
+  let ltr = open("minimal");
+
+  // Following the book
+  assert!(!rtl.right_turns_forward(None));
+  assert!(ltr.right_turns_forward(None));
+
+  // Overridden either way
+  assert!(rtl.right_turns_forward(Some(false)));
+  assert!(!rtl.right_turns_forward(Some(true)));
+  assert!(!ltr.right_turns_forward(Some(true)));
+  assert!(ltr.right_turns_forward(Some(false)));
+}
+
+#[test]
+fn images_with_the_same_name_are_extracted_apart() {
+  let mut book = open("figures.epub");
+  let chapter = book.chapter(0).expect("failed to read chapter");
+  let sources: Vec<_> = chapter
+    .blocks
+    .iter()
+    .filter_map(|block| match block {
+      RenderableBlock::Image { src, .. } => Some(src.clone()),
+      _ => None,
+    })
+    .collect();
+  assert_eq!(sources, ["red/fig.png", "blue/fig.png"]);
+
+  let red = book
+    .extract_resource(&sources[0])
+    .expect("failed to extract red");
+  let blue = book
+    .extract_resource(&sources[1])
+    .expect("failed to extract blue");
+  assert_ne!(red, blue);
+  assert_ne!(
+    std::fs::read(red).expect("failed to read red"),
+    std::fs::read(blue).expect("failed to read blue")
+  );
+}
+
+#[test]
+fn books_without_chapters_are_rejected() {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/empty.epub");
+  let error = match EpubHandler::open(path) {
+    Ok(_) => panic!("opened a book with an empty spine"),
+    Err(error) => error,
+  };
+  assert!(error.contains("no readable chapters"), "{}", error);
+}
+
+#[test]
+fn single_chapter_books_are_one_group() {
+  let mut book = open("prose.epub");
+  assert_eq!(book.get_chapter_count(), 1);
+  assert_eq!(book.first_content_chapter(), 0);
+  assert_eq!(book.chapter_group(0), 0..1);
+  assert!(book.chapter(0).is_ok());
+}
+
+// Title, depth and spine index of every TOC entry, in reading order
+fn toc_outline(book: &EpubHandler) -> Vec<(&str, usize, usize)> {
+  book
+    .toc_entries()
+    .iter()
+    .map(|entry| (entry.title.as_str(), entry.depth, entry.chapter))
+    .collect()
+}
+
+const NESTED_TOC: [(&str, usize, usize); 5] = [
+  ("Part One", 0, 0),
+  ("Chapter 1", 1, 1),
+  ("Section 1.1", 2, 1),
+  ("Chapter 2", 1, 2),
+  ("Part Two", 0, 3),
+];
+
+#[test]
+fn nested_ncx_entries_keep_their_depth_and_order() {
+  let book = open("toc-ncx.epub");
+  assert_eq!(toc_outline(&book), NESTED_TOC);
+}
+
+#[test]
+fn nested_nav_entries_keep_their_depth_and_order() {
+  let book = open("toc-nav.epub");
+  assert_eq!(toc_outline(&book), NESTED_TOC);
+}
+
+#[test]
+fn resources_without_a_file_name_are_named_by_their_path() {
+  let book = Path::new("book.epub");
+  let name = temp_file_name(book, "OEBPS/images/..");
+  assert!(name.starts_with("creb-resource-"), "{}", name);
+  assert_eq!(name, temp_file_name(book, "OEBPS/images/.."));
+  assert_ne!(name, temp_file_name(book, "OEBPS/figures/.."));
+  assert_ne!(
+    name,
+    temp_file_name(Path::new("other.epub"), "OEBPS/images/..")
+  );
+  assert!(temp_file_name(book, "").starts_with("creb-resource-"));
+
+  let named = temp_file_name(book, "OEBPS/images/fig.png");
+  assert!(
+    named.starts_with("creb-") && named.ends_with("-fig.png"),
+    "{}",
+    named
+  );
+}
+
+#[test]
+fn percent_encoded_image_names_are_extracted() {
+  let mut book = open("encoded.epub");
+  let chapter = book.chapter(0).expect("failed to read chapter");
+  let sources: Vec<_> = chapter
+    .blocks
+    .iter()
+    .filter_map(|block| match block {
+      RenderableBlock::Image { src, .. } => Some(src.clone()),
+      _ => None,
+    })
+    .collect();
+  assert_eq!(sources, ["../images/red dot.png", "../images/café.png"]);
+
+  for source in sources
+    .iter()
+    .map(String::as_str)
+    .chain(["../images/red%20dot.png", "../images/caf%C3%A9.png"])
+  {
+    let extracted = book
+      .extract_resource(source)
+      .unwrap_or_else(|e| panic!("failed to extract {}: {}", source, e));
+    let data = std::fs::read(extracted).expect("failed to read extracted image");
+    assert!(data.starts_with(b"\x89PNG"), "{} isn't the PNG", source);
+  }
+}
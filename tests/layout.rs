@@ -0,0 +1,26 @@
+use creb::{TextRun, runs_text, wrap_runs};
+
+fn wrap(text: &str, width: usize) -> Vec<String> {
+  wrap_runs(&[TextRun::plain(text)], width, 0)
+    .iter()
+    .map(|line| runs_text(&line.runs))
+    .collect()
+}
+
+#[test]
+fn lines_never_break_at_a_non_breaking_space() {
+  assert_eq!(wrap("page 12 of\u{a0}300", 10), ["page 12", "of 300"]);
+  assert_eq!(wrap("page 12 of 300", 10), ["page 12 of", "300"]);
+}
+
+#[test]
+fn soft_hyphens_only_show_where_a_word_is_broken() {
+  assert_eq!(
+    wrap("Some extra\u{ad}ordinary words", 12),
+    ["Some extra-", "ordinary", "words"]
+  );
+  assert_eq!(
+    wrap("Some extra\u{ad}ordinary words", 40),
+    ["Some extraordinary words"]
+  );
+}
@@ -0,0 +1,463 @@
+use creb::{ProcessOptions, RenderableBlock, RenderableChapter, Stylesheet, TextRun, TextStyle};
+
+fn process(body: &str) -> RenderableChapter {
+  let html = format!(
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n\
+     <html xmlns=\"http://www.w3.org/1999/xhtml\"><body>{}</body></html>",
+    body
+  );
+  creb::process_chapter_html(&html, &Stylesheet::default(), &ProcessOptions::default())
+}
+
+fn paragraph(text: &str) -> RenderableBlock {
+  RenderableBlock::Paragraph(vec![TextRun::plain(text)])
+}
+
+fn heading(level: usize, text: &str) -> RenderableBlock {
+  RenderableBlock::Heading(level, vec![TextRun::plain(text)])
+}
+
+#[test]
+fn headings_keep_their_level() {
+  let chapter =
+    process("<h1>One</h1><h2>Two</h2><h3>Three</h3><h4>Four</h4><h5>Five</h5><h6> Six </h6>");
+
+  let expected: Vec<_> = ["One", "Two", "Three", "Four", "Five", "Six"]
+    .iter()
+    .enumerate()
+    .map(|(index, text)| heading(index + 1, text))
+    .collect();
+  assert_eq!(chapter.blocks, expected);
+}
+
+#[test]
+fn paragraphs_are_trimmed_and_styled() {
+  let chapter = process("<p>  First  </p>\n<p>A <em>second</em> one</p>");
+
+  let italic = TextStyle {
+    italic: true,
+    ..TextStyle::default()
+  };
+  assert_eq!(
+    chapter.blocks,
+    [
+      paragraph("First"),
+      RenderableBlock::Paragraph(vec![
+        TextRun::plain("A "),
+        TextRun {
+          text: "second".to_string(),
+          style: italic,
+        },
+        TextRun::plain(" one"),
+      ]),
+    ]
+  );
+}
+
+#[test]
+fn text_around_nested_divs_stays_in_order() {
+  let chapter = process("<div>Before<div><p>Inside</p></div>After</div>");
+
+  assert_eq!(
+    chapter.blocks,
+    [paragraph("Before"), paragraph("Inside"), paragraph("After")]
+  );
+}
+
+#[test]
+fn images_split_the_paragraph_they_are_in() {
+  let chapter = process("<p>Before <img src=\"../images/a%20b.png\" alt=\" A \"/> after</p>");
+
+  assert_eq!(
+    chapter.blocks,
+    [
+      paragraph("Before"),
+      RenderableBlock::Image {
+        src: "../images/a b.png".to_string(),
+        alt: Some("A".to_string()),
+        caption: None,
+      },
+      paragraph("after"),
+    ]
+  );
+}
+
+#[test]
+fn svg_wrapped_covers_show_the_embedded_image() {
+  let chapter = process(
+    "<div epub:type=\"cover\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\
+     <svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+     version=\"1.1\" viewBox=\"0 0 600 800\" preserveAspectRatio=\"xMidYMid meet\">\
+     <image width=\"600\" height=\"800\" xlink:href=\"../images/cover.jpg\"/></svg></div>",
+  );
+
+  assert_eq!(
+    chapter.blocks,
+    [RenderableBlock::Image {
+      src: "../images/cover.jpg".to_string(),
+      alt: None,
+      caption: None,
+    }]
+  );
+}
+
+#[test]
+fn figure_captions_go_with_their_image() {
+  let chapter = process(
+    "<figure><figcaption><p>Above</p></figcaption><img src=\"a.png\"/></figure>\
+     <figure><img src=\"b.png\"/><figcaption>Map of <b>the</b> town</figcaption></figure>\
+     <figure><figcaption>Alone</figcaption></figure>",
+  );
+
+  let italic = |text: &str, bold| TextRun {
+    text: text.to_string(),
+    style: TextStyle {
+      bold,
+      italic: true,
+      ..TextStyle::default()
+    },
+  };
+  assert_eq!(
+    chapter.blocks,
+    [
+      RenderableBlock::Image {
+        src: "a.png".to_string(),
+        alt: None,
+        caption: Some(vec![italic("Above", false)]),
+      },
+      RenderableBlock::Image {
+        src: "b.png".to_string(),
+        alt: None,
+        caption: Some(vec![
+          italic("Map of ", false),
+          italic("the", true),
+          italic(" town", false),
+        ]),
+      },
+      RenderableBlock::Centered(vec![italic("Alone", false)]),
+    ]
+  );
+}
+
+#[test]
+fn images_without_a_source_become_placeholders() {
+  let chapter = process("<p><img alt=\"\"/></p>");
+
+  assert_eq!(
+    chapter.blocks,
+    [RenderableBlock::ImagePlaceholder(
+      "Image without source".to_string()
+    )]
+  );
+}
+
+#[test]
+fn preserved_code_is_told_apart_from_verse() {
+  let chapter = process(
+    "<pre><code>fn main() {\n    run();\n}</code></pre>\
+     <div style=\"white-space: pre-wrap\">Roses are red,\n  violets are blue</div>",
+  );
+
+  assert_eq!(
+    chapter.blocks,
+    [
+      RenderableBlock::Code(vec![TextRun::plain("fn main() {\n    run();\n}")]),
+      RenderableBlock::Preformatted(vec![TextRun::plain("Roses are red,\n  violets are blue")]),
+    ]
+  );
+}
+
+#[test]
+fn html_entities_are_decoded() {
+  let chapter = process("<p>Wait&nbsp;&mdash; &ldquo;what&rdquo;&hellip; &amp; more</p>");
+
+  assert_eq!(chapter.blocks, [paragraph("Wait\u{a0}— “what”… & more")]);
+}
+
+#[test]
+fn documents_without_blocks_fall_back_to_their_markup() {
+  let html = "<html><body></body></html>";
+  let chapter =
+    creb::process_chapter_html(html, &Stylesheet::default(), &ProcessOptions::default());
+
+  assert_eq!(chapter.blocks, [paragraph(html)]);
+}
+
+#[test]
+fn empty_spine_items_say_they_have_no_text() {
+  let chapter =
+    creb::process_chapter_html(" \n", &Stylesheet::default(), &ProcessOptions::default());
+
+  assert_eq!(
+    chapter.blocks,
+    [RenderableBlock::Centered(vec![TextRun::plain(
+      "This page has no text content"
+    )])]
+  );
+}
+
+#[test]
+fn malformed_markup_falls_back_to_one_paragraph() {
+  let html = "<html><body><p>Unclosed <b>bold</p></body></html>";
+  let chapter =
+    creb::process_chapter_html(html, &Stylesheet::default(), &ProcessOptions::default());
+
+  assert_eq!(chapter.blocks, [paragraph(html)]);
+  assert!(chapter.links.is_empty());
+}
+
+#[test]
+fn links_index_the_chapter_link_list() {
+  let chapter = process("<p>See <a href=\"notes.xhtml#n%201\">the note</a>.</p>");
+
+  assert_eq!(chapter.links, ["notes.xhtml#n 1"]);
+  let RenderableBlock::Paragraph(runs) = &chapter.blocks[0] else {
+    panic!("expected a paragraph, got {:?}", chapter.blocks[0]);
+  };
+  assert_eq!(runs[1].text, "the note");
+  assert_eq!(runs[1].style.link, Some(0));
+}
+
+#[test]
+fn element_ids_point_at_their_block() {
+  let chapter = process(
+    "<p>See note <a id=\"ref1\" href=\"#note1\">1</a>.</p>\
+     <div id=\"notes\"><p>Back to the text</p><p id=\"note1\">The note</p></div>",
+  );
+
+  assert_eq!(chapter.links, ["#note1"]);
+  assert_eq!(chapter.anchors["note1"], 2);
+  assert_eq!(chapter.blocks[2], paragraph("The note"));
+  assert_eq!(chapter.anchors["ref1"], 0);
+  assert_eq!(chapter.anchors["notes"], 1);
+}
+
+#[test]
+fn unusual_headings_get_a_level_from_one_to_six() {
+  let chapter = process(
+    "<h7>Seventh</h7>\
+     <p role=\"heading\" aria-level=\"3\">Aria</p>\
+     <div role=\"heading\">Default</div>\
+     <div role=\"heading\" aria-level=\"12\">Deep</div>\
+     <p>Body</p>",
+  );
+
+  assert_eq!(
+    chapter.blocks,
+    [
+      heading(6, "Seventh"),
+      heading(3, "Aria"),
+      heading(2, "Default"),
+      heading(6, "Deep"),
+      paragraph("Body"),
+    ]
+  );
+}
+
+#[test]
+fn void_tags_parse_closed_or_bare() {
+  let image = |src: &str| RenderableBlock::Image {
+    src: src.to_string(),
+    alt: None,
+    caption: None,
+  };
+  let expected = [
+    paragraph("One"),
+    image("a.png"),
+    paragraph("Two"),
+    image("b.png"),
+  ];
+
+  let xhtml = process("<p>One<img src=\"a.png\"/><br/>Two<hr/></p><p><img src=\"b.png\" /></p>");
+  assert_eq!(xhtml.blocks, expected);
+
+  let html = process("<p>One<img src=\"a.png\"><br>Two<hr></p><p><IMG src=\"b.png\"></img></p>");
+  assert_eq!(html.blocks, expected);
+}
+
+#[test]
+fn ruby_readings_follow_their_base_in_parentheses() {
+  let chapter = process(
+    "<p><ruby>漢字<rp>（</rp><rt>かんじ</rt><rp>）</rp></ruby>を<ruby>読<rt>よ</rt></ruby>む</p>",
+  );
+
+  assert_eq!(chapter.blocks, [paragraph("漢字(かんじ)を読(よ)む")]);
+}
+
+#[test]
+fn empty_and_ornament_paragraphs_become_scene_breaks() {
+  let chapter = process(
+    "<p>&nbsp;</p><p>One</p><p> </p><p></p><p>Two</p><p>* * *</p>\
+     <p><img src=\"a.png\"/></p><p>Three</p><p>⁂</p>",
+  );
+
+  assert_eq!(
+    chapter.blocks,
+    [
+      paragraph("One"),
+      RenderableBlock::SceneBreak,
+      paragraph("Two"),
+      RenderableBlock::SceneBreak,
+      RenderableBlock::Image {
+        src: "a.png".to_string(),
+        alt: None,
+        caption: None,
+      },
+      paragraph("Three"),
+    ]
+  );
+}
+
+#[test]
+fn headings_keep_their_inline_formatting() {
+  let chapter = process("<h2>The <em>Long</em> <a href=\"way.xhtml\">Way</a> Home</h2>");
+
+  let style = |italic, link| TextStyle {
+    italic,
+    link,
+    ..TextStyle::default()
+  };
+  let run = |text: &str, style| TextRun {
+    text: text.to_string(),
+    style,
+  };
+  assert_eq!(chapter.links, ["way.xhtml"]);
+  assert_eq!(
+    chapter.blocks,
+    [RenderableBlock::Heading(
+      2,
+      vec![
+        TextRun::plain("The "),
+        run("Long", style(true, None)),
+        TextRun::plain(" "),
+        run("Way", style(false, Some(0))),
+        TextRun::plain(" Home"),
+      ]
+    )]
+  );
+}
+
+#[test]
+fn footnote_bodies_are_collected_apart_from_the_text() {
+  let chapter = process(
+    "<section xmlns:epub=\"http://www.idpf.org/2007/ops\">\
+     <p>Text.</p>\
+     <aside epub:type=\"footnote\" id=\"n1\"><p>The <em>first</em>\n   note.</p></aside>\
+     <p>After</p>\
+     <aside epub:type=\"endnote\"><p>The second note.</p></aside></section>",
+  );
+
+  assert_eq!(chapter.blocks, [paragraph("Text."), paragraph("After")]);
+  let notes: Vec<_> = chapter
+    .footnotes
+    .iter()
+    .map(|note| note.text.as_str())
+    .collect();
+  assert_eq!(notes, ["The first note.", "The second note."]);
+}
+
+#[test]
+fn title_pages_are_centered() {
+  let chapter = process(
+    "<section xmlns:epub=\"http://www.idpf.org/2007/ops\" epub:type=\"titlepage\">\
+     <p>The Book</p><p>By <b>Someone</b></p></section><p>Body</p>",
+  );
+
+  assert_eq!(
+    chapter.blocks,
+    [
+      RenderableBlock::Centered(vec![TextRun::plain("The Book")]),
+      RenderableBlock::Centered(vec![
+        TextRun::plain("By "),
+        TextRun {
+          text: "Someone".to_string(),
+          style: TextStyle {
+            bold: true,
+            ..TextStyle::default()
+          },
+        },
+      ]),
+      paragraph("Body"),
+    ]
+  );
+}
+
+#[test]
+fn chapter_sections_give_the_chapter_its_title() {
+  let chapter = process(
+    "<h1>Part One</h1>\
+     <section xmlns:epub=\"http://www.idpf.org/2007/ops\" epub:type=\"bodymatter chapter\">\
+     <h2>The Beginning</h2><p>Text</p><h3>A Section</h3></section>",
+  );
+
+  assert_eq!(chapter.title.as_deref(), Some("The Beginning"));
+  assert_eq!(
+    chapter.blocks,
+    [
+      heading(1, "Part One"),
+      heading(2, "The Beginning"),
+      paragraph("Text"),
+      heading(3, "A Section"),
+    ]
+  );
+
+  let untitled = process("<h1>Part One</h1><p>Text</p>");
+  assert_eq!(untitled.title, None);
+}
+
+#[test]
+fn stanzas_with_preserved_space_keep_their_lines() {
+  let chapter = process(
+    "<p>Before</p>\
+     <div xml:space=\"preserve\">Tyger Tyger, burning bright,\n  \
+     In the forests of the night;\n<p>What immortal hand or eye,</p>\
+     <p>  Could frame thy fearful symmetry?</p></div>\
+     <p>After</p>",
+  );
+
+  assert_eq!(
+    chapter.blocks,
+    [
+      paragraph("Before"),
+      RenderableBlock::Preformatted(vec![TextRun::plain(
+        "Tyger Tyger, burning bright,\n  In the forests of the night;\n\
+         What immortal hand or eye,\n  Could frame thy fearful symmetry?"
+      )]),
+      paragraph("After"),
+    ]
+  );
+}
+
+#[test]
+fn nested_inline_formatting_builds_up_and_unwinds() {
+  let chapter = process(
+    "<p>Plain <b>bold <i>both <a href=\"n.xhtml\">linked</a> still</i> bold again</b> \
+     plain <u>under<em>lined</em></u>.</p>",
+  );
+
+  let run = |text: &str, bold, italic, underline, link| TextRun {
+    text: text.to_string(),
+    style: TextStyle {
+      bold,
+      italic,
+      underline,
+      link,
+    },
+  };
+  assert_eq!(chapter.links, ["n.xhtml"]);
+  assert_eq!(
+    chapter.blocks,
+    [RenderableBlock::Paragraph(vec![
+      TextRun::plain("Plain "),
+      run("bold ", true, false, false, None),
+      run("both ", true, true, false, None),
+      run("linked", true, true, false, Some(0)),
+      run(" still", true, true, false, None),
+      run(" bold again", true, false, false, None),
+      TextRun::plain(" plain "),
+      run("under", false, false, true, None),
+      run("lined", false, true, true, None),
+      TextRun::plain("."),
+    ])]
+  );
+}